@@ -0,0 +1,275 @@
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use crate::file_check::{reader_hash, same_content, HashAlgorithm};
+use crate::file_manifest::{FileEntry, FileManifest};
+use crate::hash_cache::{file_stamp, HashCache};
+
+/// Content-addressed store of every bundle published for a loader version and platform
+///
+/// layout:
+///   {root}/objects/{sha256[..2]}/{sha256}       bundle content keyed by its sha256, whatever the manifest algorithm
+///   {root}/releases/{patch_version}.pending.csv the stored files of a version hashed but not tagged yet
+///   {root}/releases/{patch_version}.csv         the stored files of each tagged version, path,size,sha256
+pub(crate) struct ArtifactStore {
+    root: PathBuf,
+}
+
+impl ArtifactStore {
+    // unity-project-folder/../host/store/loader-version/platform
+    pub fn new(project_path: &Path, loader_version: &str, platform: &str) -> Self {
+        let root = project_path
+            .parent()
+            .unwrap()
+            .join("host")
+            .join("store")
+            .join(loader_version)
+            .join(platform);
+        Self { root }
+    }
+
+    #[cfg(test)]
+    pub fn with_root(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        let shard = &hash[..hash.len().min(2)];
+        self.root.join("objects").join(shard).join(hash)
+    }
+
+    pub fn release_index(&self, patch_version: u32) -> PathBuf {
+        self.root
+            .join("releases")
+            .join(format!("{}.csv", patch_version))
+    }
+
+    fn pending_index(&self, patch_version: u32) -> PathBuf {
+        self.root
+            .join("releases")
+            .join(format!("{}.pending.csv", patch_version))
+    }
+
+    pub fn restore_path(&self, patch_version: u32) -> PathBuf {
+        self.root.join("restore").join(patch_version.to_string())
+    }
+
+    pub fn get(&self, hash: &str) -> Option<PathBuf> {
+        let path = self.object_path(hash);
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    // copy the file into the store if no object has its sha256 yet, returns true if copied,
+    // an existing object is only compared byte by byte when `verify` is set
+    pub fn put(&self, file: &Path, hash: &str, verify: bool) -> Result<bool> {
+        let path = self.object_path(hash);
+        if path.is_file() {
+            let corrupted = fs::metadata(&path)?.len() != fs::metadata(file)?.len()
+                || (verify && !same_content(&path, file)?);
+            if corrupted {
+                return Err(anyhow::anyhow!(
+                    "store object {} differs from {}, the store is corrupted",
+                    path.display(),
+                    file.display()
+                ));
+            }
+            return Ok(false);
+        }
+        fs::create_dir_all(path.parent().unwrap())?;
+        // copy to a temp file first, a broken copy must not look like a stored object
+        let temp_path = path.with_extension("tmp");
+        fs::copy(file, &temp_path)?;
+        // the hash may come from a cache, a new object must be keyed by what it holds
+        let actual = reader_hash(&mut File::open(&temp_path)?, HashAlgorithm::Sha256)?;
+        if actual != hash {
+            fs::remove_file(&temp_path)?;
+            return Err(anyhow::anyhow!(
+                "{} hashed {}, content is {}, rerun with --verify-cache",
+                file.display(),
+                hash,
+                actual
+            ));
+        }
+        fs::rename(&temp_path, &path)?;
+        Ok(true)
+    }
+
+    // store all the files of a build, the version is published by `promote` once it is tagged,
+    // a sha256 manifest gives the object keys, else they are kept in `sha256_cache`
+    pub fn stage(
+        &self,
+        folder: &Path,
+        manifest: &FileManifest,
+        patch_version: u32,
+        sha256_cache: &mut HashCache,
+        verify: bool,
+    ) -> Result<()> {
+        let mut index = FileManifest::new(HashAlgorithm::Sha256);
+        let mut added = 0;
+        for entry in manifest.entries.values() {
+            let file = folder.join(&entry.path);
+            let hash = if manifest.algorithm == HashAlgorithm::Sha256 {
+                entry.hash.clone()
+            } else {
+                let (size, mtime_ns) = file_stamp(&file)?;
+                match sha256_cache.get(&entry.path, size, mtime_ns) {
+                    Some(hash) if !verify => hash.to_string(),
+                    _ => {
+                        let hash = reader_hash(&mut File::open(&file)?, HashAlgorithm::Sha256)?;
+                        sha256_cache.insert(&entry.path, size, mtime_ns, &hash);
+                        hash
+                    }
+                }
+            };
+            if self.put(&file, &hash, verify)? {
+                added += 1;
+            }
+            index.insert(FileEntry {
+                hash,
+                ..entry.clone()
+            });
+        }
+        sha256_cache.retain(|path| manifest.entries.contains_key(path));
+        let pending_index = self.pending_index(patch_version);
+        fs::create_dir_all(pending_index.parent().unwrap())?;
+        index.save(&pending_index)?;
+        tracing::info!(
+            "store {}: version {} staged, {} new of {} files",
+            self.root.display(),
+            patch_version,
+            added,
+            index.entries.len()
+        );
+        Ok(())
+    }
+
+    // publish the staged version after it was tagged
+    pub fn promote(&self, patch_version: u32) -> Result<()> {
+        let pending_index = self.pending_index(patch_version);
+        if !pending_index.is_file() {
+            return Err(anyhow::anyhow!(
+                "store {}: version {} not staged",
+                self.root.display(),
+                patch_version
+            ));
+        }
+        fs::rename(&pending_index, self.release_index(patch_version))?;
+        tracing::info!(
+            "store {}: version {} published",
+            self.root.display(),
+            patch_version
+        );
+        Ok(())
+    }
+
+    // the stored objects of a published version by file name, none if it was published before the store
+    pub fn release_objects(&self, patch_version: u32) -> Result<Option<HashMap<String, String>>> {
+        let release_index = self.release_index(patch_version);
+        if !release_index.is_file() {
+            return Ok(None);
+        }
+        let index = FileManifest::load(&release_index)?;
+        if index.algorithm != HashAlgorithm::Sha256 {
+            return Err(anyhow::anyhow!(
+                "{} is not keyed by sha256, written by an older version of the store",
+                release_index.display()
+            ));
+        }
+        Ok(Some(index.hash_map()))
+    }
+
+    // rebuild the files of a published version into the folder
+    pub fn restore(
+        &self,
+        file_hash_map: &HashMap<String, String>,
+        des_folder: &Path,
+    ) -> Result<()> {
+        fs::create_dir_all(des_folder)?;
+        for (file_name, hash) in file_hash_map.iter() {
+            let object = self.get(hash).ok_or_else(|| {
+                anyhow::anyhow!("store object {} not found for {}", hash, file_name)
+            })?;
+            let des_file = des_folder.join(file_name);
+            if let Some(parent) = des_file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(object, des_file)?;
+        }
+        Ok(())
+    }
+}
+
+// compare two files byte by byte without loading them
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_publish_restore() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let build = root.join("build");
+        fs::create_dir_all(&build).unwrap();
+        fs::write(build.join("a.bundle"), b"aaa").unwrap();
+        fs::write(build.join("b.bundle"), b"bbb").unwrap();
+        // crc32 hashes that collide must not share an object
+        let mut manifest = FileManifest::new(HashAlgorithm::Crc32);
+        for name in ["a.bundle", "b.bundle"] {
+            manifest.insert(FileEntry {
+                path: name.to_string(),
+                size: 3,
+                hash: "AA".to_string(),
+            });
+        }
+
+        let store = ArtifactStore::with_root(&root.join("store"));
+        let mut sha256_cache = HashCache::default();
+        store
+            .stage(&build, &manifest, 3, &mut sha256_cache, false)
+            .unwrap();
+        assert!(store.release_objects(3).unwrap().is_none());
+        store.promote(3).unwrap();
+        let objects = store.release_objects(3).unwrap().unwrap();
+        assert_ne!(objects["a.bundle"], objects["b.bundle"]);
+        assert!(store.get(&objects["a.bundle"]).is_some());
+        assert!(store.get("AA").is_none());
+        assert!(!store
+            .put(&build.join("a.bundle"), &objects["a.bundle"], false)
+            .unwrap());
+        assert!(store.promote(4).is_err());
+        // the cached sha256 are reused by the next version
+        store
+            .stage(&build, &manifest, 4, &mut sha256_cache, false)
+            .unwrap();
+        store.promote(4).unwrap();
+        assert_eq!(store.release_objects(4).unwrap().unwrap(), objects);
+        // a new object is keyed by its content, not by a stale hash
+        fs::write(build.join("c.bundle"), b"ccc").unwrap();
+        assert!(store
+            .put(&build.join("c.bundle"), &"0".repeat(64), false)
+            .is_err());
+
+        let restored = root.join("restored");
+        store.restore(&objects, &restored).unwrap();
+        assert_eq!(fs::read(restored.join("b.bundle")).unwrap(), b"bbb");
+
+        // a stored object whose content went bad is an error, not a warning,
+        // its bytes are only compared when verifying
+        let a = build.join("a.bundle");
+        fs::write(store.get(&objects["a.bundle"]).unwrap(), b"aab").unwrap();
+        assert!(store.put(&a, &objects["a.bundle"], false).is_ok());
+        assert!(store.put(&a, &objects["a.bundle"], true).is_err());
+        fs::write(store.get(&objects["a.bundle"]).unwrap(), b"aa").unwrap();
+        assert!(store.put(&a, &objects["a.bundle"], false).is_err());
+    }
+}
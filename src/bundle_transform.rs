@@ -0,0 +1,249 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::folder_hash_list::list_files;
+use crate::giu_config::FileFilter;
+
+/// Obfuscation of the built bundles of a platform, so that they can not be opened directly
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub(crate) enum BundleTransform {
+    #[default]
+    None,
+    // prepend `offset` zero bytes, the client loads the bundle from the offset
+    Offset {
+        offset: u64,
+    },
+    // xor the first `length` bytes with the repeated hex key, the whole file if 0
+    Xor {
+        key: String,
+        #[serde(default)]
+        length: u64,
+    },
+}
+
+/// A transform streaming the bytes of every bundle, a bundle is never loaded whole
+pub(crate) trait BundleTransformer: Send + Sync {
+    // recorded in the manifests, tells the client how to load the bundles
+    fn kind(&self) -> String;
+    fn apply(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()>;
+}
+
+struct OffsetTransformer {
+    offset: usize,
+}
+
+impl BundleTransformer for OffsetTransformer {
+    fn kind(&self) -> String {
+        format!("offset:{}", self.offset)
+    }
+
+    fn apply(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()> {
+        std::io::copy(&mut std::io::repeat(0).take(self.offset as u64), writer)?;
+        std::io::copy(reader, writer)?;
+        Ok(())
+    }
+}
+
+struct XorTransformer {
+    key: Vec<u8>,
+    length: usize,
+}
+
+impl BundleTransformer for XorTransformer {
+    // the key itself is not recorded, the client ships with it
+    fn kind(&self) -> String {
+        if self.length == 0 {
+            "xor".to_string()
+        } else {
+            format!("xor:{}", self.length)
+        }
+    }
+
+    fn apply(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<()> {
+        let mut buffer = vec![0; 64 * 1024];
+        let mut position = 0;
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(());
+            }
+            for byte in buffer[..count].iter_mut() {
+                if self.length != 0 && position >= self.length {
+                    break;
+                }
+                *byte ^= self.key[position % self.key.len()];
+                position += 1;
+            }
+            writer.write_all(&buffer[..count])?;
+        }
+    }
+}
+
+impl BundleTransform {
+    pub fn transformer(&self) -> Result<Option<Box<dyn BundleTransformer>>> {
+        Ok(match self {
+            BundleTransform::None => None,
+            BundleTransform::Offset { offset } => {
+                if *offset == 0 {
+                    return Err(anyhow::anyhow!("Offset transform needs an offset above 0"));
+                }
+                Some(Box::new(OffsetTransformer {
+                    offset: usize::try_from(*offset)?,
+                }))
+            }
+            BundleTransform::Xor { key, length } => {
+                let key = hex::decode(key.trim())?;
+                if key.is_empty() {
+                    return Err(anyhow::anyhow!("Xor transform needs a hex key"));
+                }
+                Some(Box::new(XorTransformer {
+                    key,
+                    length: usize::try_from(*length)?,
+                }))
+            }
+        })
+    }
+}
+
+// every bundle written by Unity starts with it
+const UNITY_SIGNATURE: &[u8] = b"UnityFS";
+
+// the first bytes of a bundle once transformed
+fn transformed_header(transformer: &dyn BundleTransformer) -> Result<Vec<u8>> {
+    let mut header = Vec::new();
+    transformer.apply(&mut &UNITY_SIGNATURE[..], &mut header)?;
+    if header.starts_with(UNITY_SIGNATURE) || UNITY_SIGNATURE.starts_with(&header) {
+        return Err(anyhow::anyhow!(
+            "{} leaves the Unity signature readable, use another key",
+            transformer.kind()
+        ));
+    }
+    Ok(header)
+}
+
+fn read_header(path: &Path, len: usize) -> Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(len);
+    File::open(path)?
+        .take(len as u64)
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Transform the bundles of a platform folder written by the Unity build,
+/// only a bundle starting with the Unity signature is transformed, so a rerun never transforms it twice,
+/// returns the kind of the transform, none if the bundles are left as built
+pub(crate) fn transform_platform(
+    platform: &str,
+    platform_folder: &Path,
+    filter: &FileFilter,
+    transform: &BundleTransform,
+) -> Result<Option<String>> {
+    let transformer = transform.transformer()?;
+    let kind = transformer.as_ref().map(|transformer| transformer.kind());
+    let header = match transformer.as_ref() {
+        Some(transformer) => transformed_header(transformer.as_ref())?,
+        None => UNITY_SIGNATURE.to_vec(),
+    };
+
+    let mut bundles = 0;
+    let mut transformed = 0;
+    for (name, path) in list_files(platform_folder, filter)? {
+        // the other files of the filter, e.g. dlls and settings, are read as they are by the client
+        if !name.ends_with(".bundle") {
+            continue;
+        }
+        bundles += 1;
+        let file_header = read_header(&path, header.len().max(UNITY_SIGNATURE.len()))?;
+        if file_header.starts_with(&header) {
+            continue;
+        }
+        let Some(transformer) = transformer
+            .as_ref()
+            .filter(|_| file_header.starts_with(UNITY_SIGNATURE))
+        else {
+            return Err(anyhow::anyhow!(
+                "{} is neither a Unity bundle nor transformed with {}, rebuild the bundles of {}",
+                name,
+                kind.as_deref().unwrap_or("none"),
+                platform
+            ));
+        };
+        // write beside the bundle, a failed transform must not leave a half written one
+        let temp_file = path.with_extension("transform.tmp");
+        {
+            let mut reader = BufReader::new(File::open(&path)?);
+            let mut writer = BufWriter::new(File::create(&temp_file)?);
+            transformer.apply(&mut reader, &mut writer)?;
+            writer.flush()?;
+        }
+        fs::rename(&temp_file, &path)?;
+        transformed += 1;
+    }
+
+    if let Some(kind) = kind.as_ref() {
+        tracing::info!(
+            "Transformed {} bundles of {} with {}, {} already transformed",
+            transformed,
+            platform,
+            kind,
+            bundles - transformed
+        );
+    }
+    Ok(kind)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        fs::write(folder.join("a.bundle"), "UnityFS").unwrap();
+        fs::write(folder.join("settings.json"), "{}").unwrap();
+        let filter = FileFilter {
+            include: vec!["*.bundle".to_string(), "*.json".to_string()],
+            exclude: Vec::new(),
+        };
+
+        let xor = BundleTransform::Xor {
+            key: "0102".to_string(),
+            length: 0,
+        };
+        let kind = transform_platform("Android", folder, &filter, &xor).unwrap();
+        assert_eq!(kind.as_deref(), Some("xor"));
+        let transformed = fs::read(folder.join("a.bundle")).unwrap();
+        assert_eq!(&transformed[..2], &[b'U' ^ 1, b'n' ^ 2]);
+        assert_eq!(fs::read(folder.join("settings.json")).unwrap(), b"{}");
+
+        // a rerun leaves the transformed bundles alone, with or without a build in between
+        transform_platform("Android", folder, &filter, &xor).unwrap();
+        assert_eq!(fs::read(folder.join("a.bundle")).unwrap(), transformed);
+
+        // another transform, or none, needs a rebuild
+        let offset = BundleTransform::Offset { offset: 4 };
+        assert!(transform_platform("Android", folder, &filter, &offset).is_err());
+        assert!(transform_platform("Android", folder, &filter, &BundleTransform::None).is_err());
+        fs::write(folder.join("a.bundle"), "UnityFS").unwrap();
+        let kind = transform_platform("Android", folder, &filter, &offset).unwrap();
+        assert_eq!(kind.as_deref(), Some("offset:4"));
+        assert_eq!(
+            fs::read(folder.join("a.bundle")).unwrap(),
+            b"\0\0\0\0UnityFS"
+        );
+
+        // a key which leaves the signature readable is refused
+        let zero = BundleTransform::Xor {
+            key: "00".to_string(),
+            length: 0,
+        };
+        assert!(transform_platform("Android", folder, &filter, &zero).is_err());
+    }
+}
@@ -1,9 +1,101 @@
-pub(crate) async fn file_hash(path: &std::path::Path) -> anyhow::Result<String> {
-    use crc32fast::Hasher;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Hash algorithm of the file hash list
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HashAlgorithm {
+    #[default]
+    Crc32,
+    Sha256,
+    Blake3,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Crc32 => "crc32",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "crc32" => Some(HashAlgorithm::Crc32),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "xxh3" => Some(HashAlgorithm::Xxh3),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        use sha2::Digest;
+
+        match algorithm {
+            HashAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        match self {
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        use sha2::Digest;
+
+        fn to_hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+
+        match self {
+            // keep the upper case crc32 of the legacy file hash list
+            Hasher::Crc32(hasher) => format!("{:X}", hasher.finalize()),
+            Hasher::Sha256(hasher) => to_hex(&hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Xxh3(hasher) => format!("{:016x}", hasher.digest()),
+        }
+    }
+}
+
+pub(crate) async fn file_hash(
+    path: &std::path::Path,
+    algorithm: HashAlgorithm,
+) -> anyhow::Result<String> {
     use tokio::fs::File;
     use tokio::io::AsyncReadExt;
 
-    let mut hasher = Hasher::new();
+    let mut hasher = Hasher::new(algorithm);
     let mut file = File::open(path).await?;
     let mut buffer = [0; 8192];
     loop {
@@ -15,8 +107,49 @@ pub(crate) async fn file_hash(path: &std::path::Path) -> anyhow::Result<String>
         hasher.update(&buffer[..count]);
     }
 
-    let hash = hasher.finalize();
-    Ok(format!("{:X}", hash))
+    Ok(hasher.finalize())
+}
+
+// hash of a stream, e.g. a zip entry being extracted
+pub(crate) fn reader_hash(
+    reader: &mut impl std::io::Read,
+    algorithm: HashAlgorithm,
+) -> anyhow::Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = [0; 8192];
+    loop {
+        let count = reader.read(&mut buffer[..])?;
+
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+// compare two files in chunks, neither is loaded whole
+pub(crate) fn same_content(a: &std::path::Path, b: &std::path::Path) -> anyhow::Result<bool> {
+    use std::{fs, io::BufReader, io::Read};
+
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    let mut a = BufReader::new(fs::File::open(a)?);
+    let mut b = BufReader::new(fs::File::open(b)?);
+    let mut buffer_a = vec![0; 64 * 1024];
+    let mut buffer_b = vec![0; 64 * 1024];
+    loop {
+        let count = a.read(&mut buffer_a)?;
+        if count == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buffer_b[..count])?;
+        if buffer_a[..count] != buffer_b[..count] {
+            return Ok(false);
+        }
+    }
 }
 
 mod test {
@@ -27,11 +160,41 @@ mod test {
         let unity_path = std::path::Path::new(unity_path);
         let meta = std::fs::metadata(unity_path).unwrap();
         tracing::info!("meta: {:#?}", meta.len());
-        match super::file_hash(unity_path).await {
+        match super::file_hash(unity_path, super::HashAlgorithm::Crc32).await {
             Ok(hash) => {
                 tracing::info!("hash: {}", hash);
             }
             Err(e) => tracing::error!("error: {}", e),
         }
     }
+
+    #[tokio::test]
+    async fn test_algorithms() {
+        use super::HashAlgorithm;
+
+        let folder = tempfile::tempdir().unwrap();
+        let file = folder.path().join("abc");
+        std::fs::write(&file, b"abc").unwrap();
+
+        let expected = [
+            (HashAlgorithm::Crc32, "352441C2"),
+            (
+                HashAlgorithm::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashAlgorithm::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+            (HashAlgorithm::Xxh3, "78af5f94892f3950"),
+        ];
+        for (algorithm, hash) in expected {
+            assert_eq!(super::file_hash(&file, algorithm).await.unwrap(), hash);
+            assert_eq!(
+                super::reader_hash(&mut &b"abc"[..], algorithm).unwrap(),
+                hash
+            );
+            assert_eq!(HashAlgorithm::from_name(algorithm.name()), Some(algorithm));
+        }
+    }
 }
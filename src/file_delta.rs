@@ -0,0 +1,283 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+// delta layout:
+//   "GIUD" | version u8 | old size u64 | old crc32 u32 | new size u64 | new crc32 u32
+//   then instructions until the end:
+//   COPY: 0x01 | offset varint | length varint   -> copy bytes from the old file
+//   ADD:  0x02 | length varint | bytes            -> append literal bytes
+const DELTA_MAGIC: &[u8; 4] = b"GIUD";
+const DELTA_VERSION: u8 = 1;
+const OP_COPY: u8 = 0x01;
+const OP_ADD: u8 = 0x02;
+
+// size of the blocks indexed in the old file, matches shorter than this are stored as literals
+const BLOCK_SIZE: usize = 32;
+const HASH_BASE: u32 = 257;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn block_hash(block: &[u8]) -> u32 {
+    block.iter().fold(0u32, |h, b| {
+        h.wrapping_mul(HASH_BASE).wrapping_add(*b as u32)
+    })
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data
+            .get(*pos)
+            .ok_or_else(|| anyhow::anyhow!("delta truncated"))?;
+        *pos += 1;
+        if shift > 63 {
+            return Err(anyhow::anyhow!("delta varint overflow"));
+        }
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn read_array<const N: usize>(data: &[u8], pos: &mut usize) -> Result<[u8; N]> {
+    let bytes = data
+        .get(*pos..*pos + N)
+        .ok_or_else(|| anyhow::anyhow!("delta truncated"))?;
+    *pos += N;
+    Ok(bytes.try_into().unwrap())
+}
+
+fn flush_add(out: &mut Vec<u8>, pending: &[u8]) {
+    if pending.is_empty() {
+        return;
+    }
+    out.push(OP_ADD);
+    write_varint(out, pending.len() as u64);
+    out.extend_from_slice(pending);
+}
+
+/// Encode `new` as copy / add instructions against `old`
+pub(crate) fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(DELTA_MAGIC);
+    out.push(DELTA_VERSION);
+    out.extend_from_slice(&(old.len() as u64).to_le_bytes());
+    out.extend_from_slice(&crc32(old).to_le_bytes());
+    out.extend_from_slice(&(new.len() as u64).to_le_bytes());
+    out.extend_from_slice(&crc32(new).to_le_bytes());
+
+    // index the aligned blocks of the old file, keep the first offset of each hash
+    let mut blocks: HashMap<u32, usize> = HashMap::new();
+    for offset in (0..old.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(block_hash(&old[offset..offset + BLOCK_SIZE]))
+            .or_insert(offset);
+    }
+
+    // HASH_BASE ^ (BLOCK_SIZE - 1), to roll the leaving byte out of the hash
+    let base_pow = (1..BLOCK_SIZE).fold(1u32, |p, _| p.wrapping_mul(HASH_BASE));
+
+    let mut pending = Vec::new();
+    let mut pos = 0;
+    let mut hash = None;
+    while pos + BLOCK_SIZE <= new.len() {
+        let h = match hash {
+            Some(h) => h,
+            None => block_hash(&new[pos..pos + BLOCK_SIZE]),
+        };
+
+        let matched = blocks.get(&h).and_then(|&old_offset| {
+            if old[old_offset..old_offset + BLOCK_SIZE] != new[pos..pos + BLOCK_SIZE] {
+                return None;
+            }
+            // extend the match forward
+            let mut len = BLOCK_SIZE;
+            while old_offset + len < old.len()
+                && pos + len < new.len()
+                && old[old_offset + len] == new[pos + len]
+            {
+                len += 1;
+            }
+            // extend the match backward into the pending literals
+            let mut back = 0;
+            while back < pending.len()
+                && back < old_offset
+                && old[old_offset - back - 1] == new[pos - back - 1]
+            {
+                back += 1;
+            }
+            Some((old_offset - back, pos - back, len + back))
+        });
+
+        match matched {
+            Some((old_offset, new_offset, len)) => {
+                pending.truncate(pending.len() - (pos - new_offset));
+                flush_add(&mut out, &pending);
+                pending.clear();
+                out.push(OP_COPY);
+                write_varint(&mut out, old_offset as u64);
+                write_varint(&mut out, len as u64);
+                pos = new_offset + len;
+                hash = None;
+            }
+            None => {
+                pending.push(new[pos]);
+                if pos + BLOCK_SIZE < new.len() {
+                    let leaving = (new[pos] as u32).wrapping_mul(base_pow);
+                    hash = Some(
+                        h.wrapping_sub(leaving)
+                            .wrapping_mul(HASH_BASE)
+                            .wrapping_add(new[pos + BLOCK_SIZE] as u32),
+                    );
+                }
+                pos += 1;
+            }
+        }
+    }
+    pending.extend_from_slice(&new[pos..]);
+    flush_add(&mut out, &pending);
+
+    out
+}
+
+/// Rebuild the new file from `old` and a delta produced by `encode`
+pub(crate) fn apply(old: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    if delta.len() < 5 || &delta[..4] != DELTA_MAGIC {
+        return Err(anyhow::anyhow!("not a delta file"));
+    }
+    if delta[4] != DELTA_VERSION {
+        return Err(anyhow::anyhow!("unsupported delta version: {}", delta[4]));
+    }
+    let mut pos = 5;
+    let old_size = u64::from_le_bytes(read_array(delta, &mut pos)?);
+    let old_crc = u32::from_le_bytes(read_array(delta, &mut pos)?);
+    let new_size = u64::from_le_bytes(read_array(delta, &mut pos)?);
+    let new_crc = u32::from_le_bytes(read_array(delta, &mut pos)?);
+
+    if old.len() as u64 != old_size || crc32(old) != old_crc {
+        return Err(anyhow::anyhow!("delta base file mismatch"));
+    }
+
+    // the header is not trusted, a delta can not grow the file past the recorded size
+    let out_of_range = || anyhow::anyhow!("delta copy out of range");
+    let mut new = Vec::with_capacity(new_size.min((old.len() + delta.len()) as u64) as usize);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            OP_COPY => {
+                let offset = read_varint(delta, &mut pos)? as usize;
+                let len = read_varint(delta, &mut pos)? as usize;
+                let end = offset.checked_add(len).ok_or_else(out_of_range)?;
+                let bytes = old.get(offset..end).ok_or_else(out_of_range)?;
+                new.extend_from_slice(bytes);
+            }
+            OP_ADD => {
+                let len = read_varint(delta, &mut pos)? as usize;
+                let end = pos
+                    .checked_add(len)
+                    .ok_or_else(|| anyhow::anyhow!("delta truncated"))?;
+                let bytes = delta
+                    .get(pos..end)
+                    .ok_or_else(|| anyhow::anyhow!("delta truncated"))?;
+                new.extend_from_slice(bytes);
+                pos = end;
+            }
+            _ => return Err(anyhow::anyhow!("unknown delta instruction: {}", op)),
+        }
+        if new.len() as u64 > new_size {
+            return Err(anyhow::anyhow!("delta result mismatch"));
+        }
+    }
+
+    if new.len() as u64 != new_size || crc32(&new) != new_crc {
+        return Err(anyhow::anyhow!("delta result mismatch"));
+    }
+    Ok(new)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let old = sample(64 * 1024, 1);
+        let mut new = old.clone();
+        // modify, insert and delete some ranges
+        new[1000..1100].copy_from_slice(&sample(100, 2));
+        new.splice(20000..20000, sample(777, 3));
+        new.drain(40000..41000);
+        new.extend_from_slice(&sample(123, 4));
+
+        let delta = encode(&old, &new);
+        assert!(delta.len() < new.len() / 10);
+        assert_eq!(apply(&old, &delta).unwrap(), new);
+    }
+
+    #[test]
+    fn test_unrelated_and_empty() {
+        let old = sample(1000, 5);
+        let new = sample(1500, 6);
+        assert_eq!(apply(&old, &encode(&old, &new)).unwrap(), new);
+        assert_eq!(apply(&[], &encode(&[], &new)).unwrap(), new);
+        assert_eq!(apply(&old, &encode(&old, &[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_wrong_base() {
+        let old = sample(1000, 7);
+        let new = sample(1000, 8);
+        let delta = encode(&old, &new);
+        assert!(apply(&new, &delta).is_err());
+    }
+
+    #[test]
+    fn test_crafted() {
+        let old = sample(1000, 9);
+        let delta = encode(&old, &sample(1000, 10));
+        // new size at offset 17
+        let mut huge = delta.clone();
+        huge[17..25].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(apply(&old, &huge).is_err());
+
+        let mut header = delta[..29].to_vec();
+        header[17..25].copy_from_slice(&u64::MAX.to_le_bytes());
+        for op in [OP_COPY, OP_ADD] {
+            let mut crafted = header.clone();
+            crafted.push(op);
+            write_varint(&mut crafted, u64::MAX);
+            write_varint(&mut crafted, u64::MAX);
+            assert!(apply(&old, &crafted).is_err());
+        }
+    }
+}
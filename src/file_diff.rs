@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+/// Difference between the file hash list of a base tag and the current build
+#[derive(Debug, Default)]
+pub(crate) struct FileDiff {
+    // files added or modified since the base tag, sorted by name
+    pub changed: Vec<String>,
+    // files present at the base tag but missing now, sorted by name
+    pub removed: Vec<String>,
+}
+
+pub(crate) fn diff_file_hash_map(
+    old_file_hash_map: &HashMap<String, String>,
+    new_file_hash_map: &HashMap<String, String>,
+) -> FileDiff {
+    let mut diff = FileDiff::default();
+
+    for (file_name, new_hash) in new_file_hash_map.iter() {
+        match old_file_hash_map.get(file_name) {
+            Some(old_hash) if old_hash == new_hash => {}
+            _ => diff.changed.push(file_name.to_string()),
+        }
+    }
+
+    for file_name in old_file_hash_map.keys() {
+        if !new_file_hash_map.contains_key(file_name) {
+            diff.removed.push(file_name.to_string());
+        }
+    }
+
+    diff.changed.sort();
+    diff.removed.sort();
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let old = HashMap::from([
+            ("a.bundle".to_string(), "1".to_string()),
+            ("b.bundle".to_string(), "2".to_string()),
+            ("c.bundle".to_string(), "3".to_string()),
+        ]);
+        let new = HashMap::from([
+            ("a.bundle".to_string(), "1".to_string()),
+            ("b.bundle".to_string(), "20".to_string()),
+            ("d.bundle".to_string(), "4".to_string()),
+        ]);
+
+        let diff = diff_file_hash_map(&old, &new);
+        assert_eq!(diff.changed, vec!["b.bundle", "d.bundle"]);
+        assert_eq!(diff.removed, vec!["c.bundle"]);
+    }
+}
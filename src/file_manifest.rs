@@ -0,0 +1,239 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use crate::file_check::HashAlgorithm;
+
+// manifest layout (file-hash.csv):
+//   version 2: "#giu-manifest version=2 algorithm=sha256", then a csv with header "path,size,hash"
+//              " transform=xor" ends the first line when the bundles are transformed after the build
+//   version 1: "#algorithm=sha256", then "name,hash" lines
+//   legacy:    "name,hash" lines hashed with crc32
+pub(crate) const MANIFEST_VERSION: u32 = 2;
+const MANIFEST_HEADER: &str = "#giu-manifest";
+const V1_ALGORITHM_HEADER: &str = "#algorithm=";
+
+/// Files every package carries besides the manifest entries, written by the build
+pub(crate) fn package_extra_files(loader_version: &str) -> [String; 3] {
+    [
+        format!("catalog_{}.json", loader_version),
+        format!("catalog_{}.hash", loader_version),
+        "Version.txt".to_string(),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileEntry {
+    // path relative to the platform folder, with '/' separators
+    pub path: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// Versioned file hash list of a platform folder
+#[derive(Debug, Clone)]
+pub(crate) struct FileManifest {
+    pub version: u32,
+    pub algorithm: HashAlgorithm,
+    // bundle transform the client reverts to load the bundles, e.g. "offset:64"
+    pub transform: Option<String>,
+    // entries sorted by path
+    pub entries: BTreeMap<String, FileEntry>,
+}
+
+impl FileManifest {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            algorithm,
+            transform: None,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, entry: FileEntry) {
+        self.entries.insert(entry.path.clone(), entry);
+    }
+
+    pub fn hash_map(&self) -> HashMap<String, String> {
+        self.entries
+            .values()
+            .map(|entry| (entry.path.clone(), entry.hash.clone()))
+            .collect()
+    }
+
+    pub fn load(file: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(file)?)
+    }
+
+    pub fn save(&self, file: &Path) -> Result<()> {
+        fs::write(file, self.to_csv()?)?;
+        Ok(())
+    }
+
+    pub fn to_csv(&self) -> Result<String> {
+        let mut content = format!(
+            "{} version={} algorithm={}",
+            MANIFEST_HEADER, MANIFEST_VERSION, self.algorithm
+        );
+        if let Some(transform) = self.transform.as_ref() {
+            content.push_str(&format!(" transform={}", transform));
+        }
+        content.push('\n');
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(["path", "size", "hash"])?;
+        for entry in self.entries.values() {
+            writer.write_record([&entry.path, &entry.size.to_string(), &entry.hash])?;
+        }
+        content.push_str(&String::from_utf8(writer.into_inner()?)?);
+        Ok(content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        // the legacy manifest without header is crc32
+        let mut manifest = Self {
+            version: 0,
+            algorithm: HashAlgorithm::Crc32,
+            transform: None,
+            entries: BTreeMap::new(),
+        };
+
+        let first_line = content.lines().next().unwrap_or_default().trim();
+        let body = if let Some(header) = first_line.strip_prefix(MANIFEST_HEADER) {
+            for field in header.split_whitespace() {
+                match field.split_once('=') {
+                    Some(("version", version)) => manifest.version = version.parse()?,
+                    Some(("algorithm", name)) => {
+                        manifest.algorithm = HashAlgorithm::from_name(name)
+                            .ok_or_else(|| anyhow::anyhow!("Unknown hash algorithm: {}", name))?
+                    }
+                    Some(("transform", kind)) => manifest.transform = Some(kind.to_string()),
+                    _ => {}
+                }
+            }
+            if manifest.version > MANIFEST_VERSION {
+                return Err(anyhow::anyhow!(
+                    "Unsupported manifest version: {}",
+                    manifest.version
+                ));
+            }
+            content.split_once('\n').map(|(_, body)| body).unwrap_or("")
+        } else if let Some(name) = first_line.strip_prefix(V1_ALGORITHM_HEADER) {
+            manifest.version = 1;
+            manifest.algorithm = HashAlgorithm::from_name(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown hash algorithm: {}", name))?;
+            content.split_once('\n').map(|(_, body)| body).unwrap_or("")
+        } else {
+            content
+        };
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(manifest.version >= 2)
+            .flexible(true)
+            .from_reader(body.as_bytes());
+        for record in reader.records() {
+            let record = record?;
+            let entry = if manifest.version >= 2 {
+                if record.len() != 3 {
+                    return Err(anyhow::anyhow!("Invalid manifest record: {:?}", record));
+                }
+                FileEntry {
+                    path: record[0].to_string(),
+                    size: record[1].trim().parse()?,
+                    hash: record[2].trim().to_string(),
+                }
+            } else {
+                // name,hash without size
+                if record.len() != 2 {
+                    continue;
+                }
+                FileEntry {
+                    path: record[0].trim().to_string(),
+                    size: 0,
+                    hash: record[1].trim().to_string(),
+                }
+            };
+            manifest.insert(entry);
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Write records as csv lines, fields with ',' or '"' are quoted
+pub(crate) fn csv_records<I, R, F>(records: I) -> Result<String>
+where
+    I: IntoIterator<Item = R>,
+    R: IntoIterator<Item = F>,
+    F: AsRef<[u8]>,
+{
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .from_writer(Vec::new());
+    for record in records {
+        writer.write_record(record)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut manifest = FileManifest::new(HashAlgorithm::Sha256);
+        manifest.insert(FileEntry {
+            path: "b,with \"comma\".bundle".to_string(),
+            size: 42,
+            hash: "abcd".to_string(),
+        });
+        manifest.insert(FileEntry {
+            path: "a.bundle".to_string(),
+            size: 7,
+            hash: "1234".to_string(),
+        });
+
+        let content = manifest.to_csv().unwrap();
+        assert!(content
+            .starts_with("#giu-manifest version=2 algorithm=sha256\npath,size,hash\na.bundle,"));
+
+        let loaded = FileManifest::parse(&content).unwrap();
+        assert_eq!(loaded.version, MANIFEST_VERSION);
+        assert_eq!(loaded.algorithm, HashAlgorithm::Sha256);
+        assert_eq!(loaded.transform, None);
+        assert_eq!(loaded.entries, manifest.entries);
+
+        manifest.transform = Some("offset:64".to_string());
+        let content = manifest.to_csv().unwrap();
+        assert!(
+            content.starts_with("#giu-manifest version=2 algorithm=sha256 transform=offset:64\n")
+        );
+        let loaded = FileManifest::parse(&content).unwrap();
+        assert_eq!(loaded.transform.as_deref(), Some("offset:64"));
+    }
+
+    #[test]
+    fn test_legacy() {
+        let legacy = FileManifest::parse("a.bundle,1A2B\nb.bundle,3C4D\nbroken\n").unwrap();
+        assert_eq!(legacy.version, 0);
+        assert_eq!(legacy.algorithm, HashAlgorithm::Crc32);
+        assert_eq!(legacy.hash_map()["b.bundle"], "3C4D");
+        assert_eq!(legacy.entries.len(), 2);
+
+        let v1 = FileManifest::parse("#algorithm=xxh3\na.bundle,0011\n").unwrap();
+        assert_eq!(v1.version, 1);
+        assert_eq!(v1.algorithm, HashAlgorithm::Xxh3);
+        assert_eq!(v1.hash_map()["a.bundle"], "0011");
+    }
+
+    #[test]
+    fn test_csv_records() {
+        let content =
+            csv_records([["a,b.bundle", "1", "full"], ["c.bundle", "2", "delta"]]).unwrap();
+        assert_eq!(content, "\"a,b.bundle\",1,full\nc.bundle,2,delta\n");
+    }
+}
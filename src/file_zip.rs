@@ -1,14 +1,81 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
 use anyhow::Result;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::time::Instant;
 use zip::write::SimpleFileOptions;
 
+use crate::giu_config::{CompressionRule, EncryptionConfig};
+
+/// Compression of a zip entry, `auto` stores the entry when Deflate does not make it smaller
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CompressionMethod {
+    Stored,
+    #[default]
+    Deflate,
+    Zstd,
+    Bzip2,
+    Auto,
+}
+
+/// Compression method and level of each entry, the first matching rule wins, Deflate otherwise
+#[derive(Default, Clone)]
+pub(crate) struct CompressionPolicy {
+    rules: Vec<(GlobMatcher, CompressionMethod, Option<i64>)>,
+}
+
+impl CompressionPolicy {
+    pub fn new(rules: &[CompressionRule]) -> Result<Self> {
+        let mut policy = Self::default();
+        for rule in rules.iter() {
+            let matcher = GlobBuilder::new(&rule.pattern)
+                .literal_separator(true)
+                .build()?
+                .compile_matcher();
+            policy.rules.push((matcher, rule.method, rule.level));
+        }
+        Ok(policy)
+    }
+
+    pub fn method(&self, name: &str) -> (CompressionMethod, Option<i64>) {
+        self.rules
+            .iter()
+            .find(|(matcher, _, _)| matcher.is_match(name))
+            .map(|(_, method, level)| (*method, *level))
+            .unwrap_or((CompressionMethod::Deflate, None))
+    }
+}
+
 pub(crate) fn extract(src_file: &Path, des_folder: &Path) -> Result<i32> {
     let file = File::open(src_file)?;
 
     let mut archive = zip::ZipArchive::new(file)?;
 
+    extract_archive(&mut archive, des_folder)
+}
+
+// extract a package, decrypting it first when it is encrypted
+pub(crate) fn extract_package(
+    src_file: &Path,
+    des_folder: &Path,
+    keys: &PackageKeys,
+) -> Result<i32> {
+    let mut archive = open_package(src_file, keys)?;
+
+    extract_archive(&mut archive, des_folder)
+}
+
+fn extract_archive<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    des_folder: &Path,
+) -> Result<i32> {
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
         let out_path = match file.enclosed_name() {
@@ -56,16 +123,413 @@ pub(crate) fn extract(src_file: &Path, des_folder: &Path) -> Result<i32> {
     Ok(0)
 }
 
+// encrypted package layout:
+//   "GIUE" | version u8 | key id length u8 | key id | nonce prefix 8 bytes | chunk size u32
+//   then the zip in chunks of chunk size, the last one shorter or empty,
+//   each AES-256-GCM encrypted with its 16 bytes tag appended,
+//   nonce: nonce prefix | chunk index u32 big endian, aad: the header | 1 for the last chunk, 0 otherwise
+// the nonce prefix comes from the digest of the zip, the same zip and key always give the same bytes
+const ENCRYPTED_MAGIC: &[u8; 4] = b"GIUE";
+const ENCRYPTED_VERSION: u8 = 1;
+const CHUNK_SIZE: usize = 1024 * 1024;
+const TAG_SIZE: usize = 16;
+
+/// AES-256-GCM key of the packages
+#[derive(Clone)]
+pub(crate) struct PackageKey {
+    pub id: String,
+    key: [u8; 32],
+}
+
+impl PackageKey {
+    pub fn new(id: &str, key: [u8; 32]) -> Self {
+        Self {
+            id: id.to_string(),
+            key,
+        }
+    }
+}
+
+/// The key encrypting the new packages, and every key by id to decrypt the published ones
+#[derive(Clone, Default)]
+pub(crate) struct PackageKeys {
+    pub current: Option<PackageKey>,
+    keys: BTreeMap<String, PackageKey>,
+}
+
+impl PackageKeys {
+    pub fn load(config: Option<&EncryptionConfig>, project_path: &Path) -> Result<Self> {
+        let mut keys = Self::default();
+        let Some(config) = config else {
+            return Ok(keys);
+        };
+        for (id, source) in config.keys.iter() {
+            let key = source
+                .load(project_path)?
+                .ok_or_else(|| anyhow::anyhow!("No key_file or key_env for key {}", id))?;
+            keys.insert(PackageKey::new(id, key));
+        }
+        keys.current = Some(
+            keys.keys
+                .get(&config.key_id)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Encryption key {} not found", config.key_id))?,
+        );
+        Ok(keys)
+    }
+
+    pub fn insert(&mut self, key: PackageKey) {
+        self.keys.insert(key.id.clone(), key);
+    }
+
+    fn get(&self, id: &str) -> Result<&PackageKey> {
+        self.keys
+            .get(id)
+            .ok_or_else(|| anyhow::anyhow!("Encryption key {} not configured", id))
+    }
+}
+
+fn chunk_nonce(prefix: &[u8], index: u32) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..8].copy_from_slice(prefix);
+    nonce[8..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+fn chunk_aad(header: &[u8], last: bool) -> Vec<u8> {
+    let mut aad = header.to_vec();
+    aad.push(last as u8);
+    aad
+}
+
+// read until the buffer is full or the end of the file, returns the bytes read
+fn read_chunk(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let count = reader.read(&mut buffer[filled..])?;
+        if count == 0 {
+            break;
+        }
+        filled += count;
+    }
+    Ok(filled)
+}
+
+/// Id of the key an encrypted package was encrypted with, none for a plain zip
+pub(crate) fn package_key_id(file: &Path) -> Result<Option<String>> {
+    let mut reader = File::open(file)?;
+    let mut magic = [0; 6];
+    if read_chunk(&mut reader, &mut magic)? < magic.len() || &magic[..4] != ENCRYPTED_MAGIC {
+        return Ok(None);
+    }
+    let mut id = vec![0; magic[5] as usize];
+    reader.read_exact(&mut id)?;
+    Ok(Some(String::from_utf8(id)?))
+}
+
+/// Encrypt a zip in place
+pub(crate) fn encrypt_package(file: &Path, key: &PackageKey) -> Result<()> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(file)?, &mut hasher)?;
+    let digest = hasher.finalize();
+
+    let mut header = ENCRYPTED_MAGIC.to_vec();
+    header.push(ENCRYPTED_VERSION);
+    header.push(u8::try_from(key.id.len())?);
+    header.extend_from_slice(key.id.as_bytes());
+    header.extend_from_slice(&digest[..8]);
+    header.extend_from_slice(&(CHUNK_SIZE as u32).to_le_bytes());
+
+    let cipher = Aes256Gcm::new(&key.key.into());
+    let temp_file = file.with_extension("enc.tmp");
+    {
+        let mut reader = BufReader::new(File::open(file)?);
+        let mut writer = BufWriter::new(File::create(&temp_file)?);
+        writer.write_all(&header)?;
+
+        // read one chunk ahead to know which one is the last
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut next = vec![0; CHUNK_SIZE];
+        let mut len = read_chunk(&mut reader, &mut chunk)?;
+        let mut index = 0u32;
+        loop {
+            let next_len = if len == CHUNK_SIZE {
+                read_chunk(&mut reader, &mut next)?
+            } else {
+                0
+            };
+            let last = next_len == 0;
+            let nonce = chunk_nonce(&digest[..8], index);
+            let aad = chunk_aad(&header, last);
+            let encrypted = cipher
+                .encrypt(
+                    Nonce::from_slice(&nonce),
+                    Payload {
+                        msg: &chunk[..len],
+                        aad: &aad,
+                    },
+                )
+                .map_err(|_| anyhow::anyhow!("Failed to encrypt {}", file.display()))?;
+            writer.write_all(&encrypted)?;
+            if last {
+                break;
+            }
+            std::mem::swap(&mut chunk, &mut next);
+            len = next_len;
+            index += 1;
+        }
+        writer.flush()?;
+    }
+    fs::rename(&temp_file, file)?;
+    Ok(())
+}
+
+// decrypt an encrypted package into the writer
+fn decrypt_package(file: &Path, keys: &PackageKeys, writer: &mut impl Write) -> Result<()> {
+    let mut reader = BufReader::new(File::open(file)?);
+    let mut header = vec![0; 6];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != ENCRYPTED_MAGIC || header[4] != ENCRYPTED_VERSION {
+        return Err(anyhow::anyhow!(
+            "{} is not an encrypted package",
+            file.display()
+        ));
+    }
+    let mut rest = vec![0; header[5] as usize + 8 + 4];
+    reader.read_exact(&mut rest)?;
+    header.extend_from_slice(&rest);
+    let id = std::str::from_utf8(&rest[..rest.len() - 12])?;
+    let prefix = rest[rest.len() - 12..rest.len() - 4].to_vec();
+    // the header is not trusted before the first chunk is authenticated, never allocate from it
+    let chunk_size = u32::from_le_bytes(rest[rest.len() - 4..].try_into()?) as usize;
+    if chunk_size != CHUNK_SIZE {
+        return Err(anyhow::anyhow!(
+            "{} has an unsupported chunk size {}",
+            file.display(),
+            chunk_size
+        ));
+    }
+
+    let cipher = Aes256Gcm::new(&keys.get(id)?.key.into());
+    let mut chunk = vec![0; chunk_size + TAG_SIZE];
+    let mut next = vec![0; chunk_size + TAG_SIZE];
+    let mut len = read_chunk(&mut reader, &mut chunk)?;
+    let mut index = 0u32;
+    loop {
+        let next_len = if len == chunk.len() {
+            read_chunk(&mut reader, &mut next)?
+        } else {
+            0
+        };
+        let last = next_len == 0;
+        let nonce = chunk_nonce(&prefix, index);
+        let aad = chunk_aad(&header, last);
+        let decrypted = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &chunk[..len],
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt {} with key {}", file.display(), id))?;
+        writer.write_all(&decrypted)?;
+        if last {
+            return Ok(());
+        }
+        std::mem::swap(&mut chunk, &mut next);
+        len = next_len;
+        index += 1;
+    }
+}
+
+/// Open a package, an encrypted one is decrypted into a temporary file first
+pub(crate) fn open_package(file: &Path, keys: &PackageKeys) -> Result<zip::ZipArchive<File>> {
+    if package_key_id(file)?.is_none() {
+        return Ok(zip::ZipArchive::new(File::open(file)?)?);
+    }
+    let mut decrypted = tempfile::tempfile()?;
+    let mut writer = BufWriter::new(&mut decrypted);
+    decrypt_package(file, keys, &mut writer)?;
+    // a failed write, e.g. a full disk, must not show up later as a broken zip
+    writer.flush()?;
+    drop(writer);
+    decrypted.seek(SeekFrom::Start(0))?;
+    Ok(zip::ZipArchive::new(decrypted)?)
+}
+
+const DEFLATE_LEVEL: i64 = 6;
+
+// fixed timestamp, permissions and compression level, the same files always give the same bytes
+fn entry_options(method: CompressionMethod, level: Option<i64>) -> SimpleFileOptions {
+    let (method, level) = match method {
+        CompressionMethod::Stored => (zip::CompressionMethod::Stored, None),
+        CompressionMethod::Deflate | CompressionMethod::Auto => (
+            zip::CompressionMethod::Deflated,
+            Some(level.unwrap_or(DEFLATE_LEVEL)),
+        ),
+        CompressionMethod::Zstd => (zip::CompressionMethod::Zstd, level),
+        CompressionMethod::Bzip2 => (zip::CompressionMethod::Bzip2, level),
+    };
+    SimpleFileOptions::default()
+        .compression_method(method)
+        .compression_level(level)
+        .last_modified_time(zip::DateTime::default())
+        .unix_permissions(0o644)
+}
+
+// log the progress of a file every 64M
+const PROGRESS_STEP: u64 = 64 * 1024 * 1024;
+
+// reader counting the bytes read, logs the progress of large files
+struct ProgressReader<R> {
+    inner: R,
+    name: String,
+    total: u64,
+    read: u64,
+    next_report: u64,
+}
+
+impl<R: Read> ProgressReader<R> {
+    fn new(inner: R, name: &str, total: u64) -> Self {
+        Self {
+            inner,
+            name: name.to_string(),
+            total,
+            read: 0,
+            next_report: PROGRESS_STEP,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.read += count as u64;
+        if self.read >= self.next_report {
+            tracing::info!(
+                "{}: {} / {} M",
+                self.name,
+                self.read / 1048576,
+                self.total / 1048576
+            );
+            self.next_report += PROGRESS_STEP;
+        }
+        Ok(count)
+    }
+}
+
+fn stream_file<W: Write + Seek>(
+    zip_writer: &mut zip::ZipWriter<W>,
+    entry_name: &str,
+    options: SimpleFileOptions,
+    path: &Path,
+) -> Result<u64> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    zip_writer.start_file(entry_name, options)?;
+    std::io::copy(&mut ProgressReader::new(file, entry_name, size), zip_writer)?;
+    Ok(size)
+}
+
+// deflate the file into a temp zip, copy the entry as is when it gets smaller, stored otherwise
+fn write_auto<W: Write + Seek>(
+    zip_writer: &mut zip::ZipWriter<W>,
+    entry_name: &str,
+    level: Option<i64>,
+    path: &Path,
+    temp_file: &Path,
+) -> Result<u64> {
+    let temp_zip = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(temp_file)?;
+    let mut temp_writer = zip::ZipWriter::new(temp_zip);
+    let size = stream_file(
+        &mut temp_writer,
+        entry_name,
+        entry_options(CompressionMethod::Deflate, level),
+        path,
+    )?;
+    let mut temp_zip = temp_writer.finish()?;
+    temp_zip.seek(SeekFrom::Start(0))?;
+    {
+        let mut temp_archive = zip::ZipArchive::new(temp_zip)?;
+        let entry = temp_archive.by_index_raw(0)?;
+        if entry.compressed_size() < size {
+            zip_writer.raw_copy_file(entry)?;
+        } else {
+            tracing::info!("store {entry_name}, not smaller after compression");
+            stream_file(
+                zip_writer,
+                entry_name,
+                entry_options(CompressionMethod::Stored, None),
+                path,
+            )?;
+        }
+    }
+    fs::remove_file(temp_file)?;
+    Ok(size)
+}
+
+/// Files compressed once per run, raw copied into every zip which needs them
+pub(crate) struct EntryCache {
+    archive: zip::ZipArchive<File>,
+}
+
+impl EntryCache {
+    pub fn build(
+        prefix: &Path,
+        src_files: &[&String],
+        cache_file: &Path,
+        policy: &CompressionPolicy,
+    ) -> Result<Self> {
+        compress(prefix, src_files, cache_file, false, policy)?;
+        Self::open(cache_file)
+    }
+
+    // every reader opens the cache on its own, raw copies seek in the archive
+    pub fn open(cache_file: &Path) -> Result<Self> {
+        Ok(Self {
+            archive: zip::ZipArchive::new(File::open(cache_file)?)?,
+        })
+    }
+}
+
 pub(crate) fn compress(
     prefix: &Path,
     src_files: &[&String],
     des_file: &Path,
     append: bool,
+    policy: &CompressionPolicy,
+) -> anyhow::Result<()> {
+    write_zip(prefix, src_files, des_file, append, policy, None)
+}
+
+// the entries found in the cache are raw copied, the other files are compressed
+pub(crate) fn compress_with_cache(
+    prefix: &Path,
+    src_files: &[&String],
+    des_file: &Path,
+    append: bool,
+    policy: &CompressionPolicy,
+    cache: &mut EntryCache,
+) -> anyhow::Result<()> {
+    write_zip(prefix, src_files, des_file, append, policy, Some(cache))
+}
+
+fn write_zip(
+    prefix: &Path,
+    src_files: &[&String],
+    des_file: &Path,
+    append: bool,
+    policy: &CompressionPolicy,
+    mut cache: Option<&mut EntryCache>,
 ) -> anyhow::Result<()> {
     // will only use this for zip, if use this function for other compression may cause error
-    let options = SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
+    let dir_options = entry_options(CompressionMethod::Stored, None).unix_permissions(0o755);
 
     let mut zip_writer = if append {
         let existing_zip = OpenOptions::new().read(true).write(true).open(des_file)?;
@@ -78,29 +542,253 @@ pub(crate) fn compress(
         zip::ZipWriter::new(file)
     };
 
+    // sorted entries, the order of the caller may be random
+    let mut src_files = src_files.to_vec();
+    src_files.sort();
+    src_files.dedup();
+
     let prefix = Path::new(prefix);
-    let mut buffer = Vec::new();
-    for name in src_files.iter() {
+    let temp_file = des_file.with_extension("auto.tmp");
+    let start = Instant::now();
+    let mut total_size = 0;
+    for (i, name) in src_files.iter().enumerate() {
         let path = prefix.join(name);
         let relative = path.strip_prefix(prefix).unwrap();
+        // zip entries always use '/' separators
+        let entry_name = name.replace('\\', "/");
+
+        let cached = cache
+            .as_deref_mut()
+            .and_then(|cache| Some((cache.archive.index_for_name(&entry_name)?, cache)));
 
         // Write file or directory explicitly
         // Some unzip tools unzip files with directory paths correctly, some do not!
-        if path.is_file() {
-            tracing::info!("adding file {path:?} as {name:?} ...");
-            zip_writer.start_file(relative.to_str().unwrap(), options)?;
-            let mut f = File::open(path)?;
-
-            f.read_to_end(&mut buffer)?;
-            zip_writer.write_all(&buffer)?;
-            buffer.clear();
+        if let Some((index, cache)) = cached {
+            tracing::info!(
+                "[{}/{}] copying compressed {name:?} ...",
+                i + 1,
+                src_files.len()
+            );
+            let entry = cache.archive.by_index_raw(index)?;
+            total_size += entry.size();
+            zip_writer.raw_copy_file(entry)?;
+        } else if path.is_file() {
+            let (method, level) = policy.method(&entry_name);
+            tracing::info!(
+                "[{}/{}] adding file {path:?} as {name:?} ({method:?}) ...",
+                i + 1,
+                src_files.len()
+            );
+            total_size += if method == CompressionMethod::Auto {
+                write_auto(&mut zip_writer, &entry_name, level, &path, &temp_file)?
+            } else {
+                stream_file(
+                    &mut zip_writer,
+                    &entry_name,
+                    entry_options(method, level),
+                    &path,
+                )?
+            };
         } else if !relative.as_os_str().is_empty() {
             // Only if not root! Avoids path spec / warning
             // and map name conversion failed error on unzip
             tracing::info!("adding dir {relative:?} as {name:?} ...");
-            zip_writer.add_directory(relative.to_str().unwrap(), options)?;
+            zip_writer.add_directory(entry_name.as_str(), dir_options)?;
         }
     }
     zip_writer.finish()?;
+
+    let elapsed = start.elapsed().as_secs_f64();
+    tracing::info!(
+        "{}: {} files, {:.2} M in {:.2} s, {:.2} M/s, archive {:.2} M",
+        des_file.display(),
+        src_files.len(),
+        total_size as f64 / 1048576_f64,
+        elapsed,
+        total_size as f64 / 1048576_f64 / elapsed.max(0.001),
+        fs::metadata(des_file)?.len() as f64 / 1048576_f64
+    );
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reproducible() {
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        fs::create_dir_all(folder.join("sub")).unwrap();
+        fs::write(folder.join("a.bundle"), vec![1u8; 4096]).unwrap();
+        fs::write(folder.join("b.bundle"), b"bbb").unwrap();
+        fs::write(folder.join("sub/c.bytes"), b"ccc").unwrap();
+
+        let names = ["b.bundle", "sub/c.bytes", "a.bundle"].map(|n| n.to_string());
+        let forward = names.iter().collect::<Vec<_>>();
+        let backward = names.iter().rev().collect::<Vec<_>>();
+
+        let first = folder.join("first.zip");
+        let second = folder.join("second.zip");
+        let policy = CompressionPolicy::default();
+        compress(folder, &forward, &first, false, &policy).unwrap();
+        compress(folder, &backward, &second, false, &policy).unwrap();
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+
+        // append streams into the existing archive
+        fs::write(folder.join("d.bundle"), b"ddd").unwrap();
+        let appended = "d.bundle".to_string();
+        compress(folder, &[&appended], &second, true, &policy).unwrap();
+        let archive = zip::ZipArchive::new(File::open(&second).unwrap()).unwrap();
+        assert_eq!(archive.len(), 4);
+
+        let out = folder.join("out");
+        extract(&first, &out).unwrap();
+        assert_eq!(fs::read(out.join("sub/c.bytes")).unwrap(), b"ccc");
+    }
+
+    #[test]
+    fn test_policy() {
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        // pseudo random bytes do not compress
+        let mut x = 1u32;
+        let noise = (0..4096)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+        fs::write(folder.join("noise.bundle"), &noise).unwrap();
+        fs::write(folder.join("text.bundle"), vec![b'a'; 4096]).unwrap();
+        fs::write(folder.join("data.bytes"), vec![b'b'; 4096]).unwrap();
+        fs::write(folder.join("data.json"), vec![b'c'; 4096]).unwrap();
+
+        let rules = [
+            ("*.bundle", CompressionMethod::Auto),
+            ("*.bytes", CompressionMethod::Zstd),
+            ("*.json", CompressionMethod::Bzip2),
+        ]
+        .map(|(pattern, method)| CompressionRule {
+            pattern: pattern.to_string(),
+            method,
+            level: None,
+        });
+        let policy = CompressionPolicy::new(&rules).unwrap();
+        assert_eq!(policy.method("x.txt"), (CompressionMethod::Deflate, None));
+
+        let names =
+            ["noise.bundle", "text.bundle", "data.bytes", "data.json"].map(|n| n.to_string());
+        let zip_file = folder.join("policy.zip");
+        compress(
+            folder,
+            &names.iter().collect::<Vec<_>>(),
+            &zip_file,
+            false,
+            &policy,
+        )
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&zip_file).unwrap()).unwrap();
+        let methods = [
+            ("noise.bundle", zip::CompressionMethod::Stored),
+            ("text.bundle", zip::CompressionMethod::Deflated),
+            ("data.bytes", zip::CompressionMethod::Zstd),
+            ("data.json", zip::CompressionMethod::Bzip2),
+        ];
+        for (name, method) in methods {
+            assert_eq!(archive.by_name(name).unwrap().compression(), method);
+        }
+
+        let out = folder.join("out");
+        extract(&zip_file, &out).unwrap();
+        assert_eq!(fs::read(out.join("noise.bundle")).unwrap(), noise);
+        assert_eq!(fs::read(out.join("data.bytes")).unwrap(), vec![b'b'; 4096]);
+    }
+
+    #[test]
+    fn test_entry_cache() {
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        fs::write(folder.join("a.bundle"), vec![1u8; 4096]).unwrap();
+        fs::write(folder.join("b.bundle"), b"bbb").unwrap();
+        fs::write(folder.join("diff.csv"), b"a.bundle").unwrap();
+
+        let names = ["a.bundle", "b.bundle", "diff.csv"].map(|n| n.to_string());
+        let names = names.iter().collect::<Vec<_>>();
+        let policy = CompressionPolicy::default();
+        let mut cache =
+            EntryCache::build(folder, &names[..2], &folder.join("cache.zip"), &policy).unwrap();
+
+        // copied entries give the same bytes as compressing the files again
+        let direct = folder.join("direct.zip");
+        let cached = folder.join("cached.zip");
+        compress(folder, &names, &direct, false, &policy).unwrap();
+        compress_with_cache(folder, &names, &cached, false, &policy, &mut cache).unwrap();
+        assert_eq!(fs::read(&direct).unwrap(), fs::read(&cached).unwrap());
+    }
+
+    #[test]
+    fn test_encrypt() {
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        // incompressible, so the zip spans several chunks
+        let mut seed = 1u32;
+        let random = (0..CHUNK_SIZE * 3 / 2)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect::<Vec<_>>();
+        fs::write(folder.join("a.bundle"), &random).unwrap();
+        let names = ["a.bundle".to_string()];
+        let package = folder.join("1-1.zip");
+        compress(
+            folder,
+            &names.iter().collect::<Vec<_>>(),
+            &package,
+            false,
+            &CompressionPolicy::default(),
+        )
+        .unwrap();
+        let plain = fs::read(&package).unwrap();
+
+        let key = PackageKey::new("k1", [1; 32]);
+        let mut keys = PackageKeys::default();
+        keys.insert(key.clone());
+        assert!(package_key_id(&package).unwrap().is_none());
+        encrypt_package(&package, &key).unwrap();
+        assert_eq!(package_key_id(&package).unwrap().as_deref(), Some("k1"));
+        let encrypted = fs::read(&package).unwrap();
+        assert_ne!(encrypted, plain);
+
+        // the same zip and key give the same bytes
+        fs::write(&package, &plain).unwrap();
+        encrypt_package(&package, &key).unwrap();
+        assert_eq!(fs::read(&package).unwrap(), encrypted);
+
+        let out = folder.join("out");
+        extract_package(&package, &out, &keys).unwrap();
+        assert_eq!(fs::read(out.join("a.bundle")).unwrap(), random);
+
+        // an unknown key, a wrong key, a flipped byte and a dropped chunk are rejected
+        assert!(open_package(&package, &PackageKeys::default()).is_err());
+        let mut wrong = PackageKeys::default();
+        wrong.insert(PackageKey::new("k1", [2; 32]));
+        assert!(open_package(&package, &wrong).is_err());
+        let mut tampered = encrypted.clone();
+        tampered[100] ^= 1;
+        fs::write(&package, &tampered).unwrap();
+        assert!(open_package(&package, &keys).is_err());
+        fs::write(&package, &encrypted[..encrypted.len() - 100]).unwrap();
+        assert!(open_package(&package, &keys).is_err());
+        // a crafted chunk size is rejected before anything is allocated from it
+        let mut crafted = encrypted.clone();
+        let chunk_size_at = 6 + "k1".len() + 8;
+        crafted[chunk_size_at..chunk_size_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&package, &crafted).unwrap();
+        assert!(open_package(&package, &keys).is_err());
+    }
+}
@@ -1,34 +1,158 @@
-pub(crate) async fn folder_hash_list(folder: &std::path::Path) -> anyhow::Result<String> {
+use anyhow::Result;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::{Path, PathBuf};
+
+use crate::file_manifest::{FileEntry, FileManifest};
+use crate::giu_config::FileFilter;
+use crate::hash_cache::{file_stamp, HashCache};
+
+// files generated by this tool in the platform folder, never part of the file hash list
+const GENERATED_FILES: &[&str] = &[
+    "file-hash.csv",
+    "Version.txt",
+    "diff-*.csv",
+    "remove-*.csv",
+    "catalog_*.json",
+    "catalog_*.hash",
+];
+
+fn glob_set(patterns: &[impl AsRef<str>]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.iter() {
+        // `*` does not match '/', use `**` to match folders
+        builder.add(
+            GlobBuilder::new(pattern.as_ref())
+                .literal_separator(true)
+                .build()?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+// walk the folder recursively, returns the matched files relative to the folder with '/' separators
+pub(crate) fn list_files(folder: &Path, filter: &FileFilter) -> Result<Vec<(String, PathBuf)>> {
+    let include = glob_set(&filter.include)?;
+    let exclude = glob_set(&filter.exclude)?;
+    let generated = glob_set(GENERATED_FILES)?;
+
+    let mut files = Vec::new();
+    let mut folders = vec![folder.to_path_buf()];
+    while let Some(current) = folders.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                folders.push(path);
+                continue;
+            }
+            let relative = path
+                .strip_prefix(folder)?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if generated.is_match(&relative) {
+                continue;
+            }
+            if include.is_match(&relative) && !exclude.is_match(&relative) {
+                files.push((relative, path));
+            } else {
+                tracing::debug!("skip file: {}", relative);
+            }
+        }
+    }
+    Ok(files)
+}
+
+// hash the files of the folder, reuse the cached hash of files with unchanged size and mtime,
+// `verify_cache` rehashes every file and reports the cached hashes which went stale
+pub(crate) async fn folder_hash_list(
+    folder: &std::path::Path,
+    filter: &FileFilter,
+    algorithm: crate::file_check::HashAlgorithm,
+    workers: usize,
+    cache: &mut HashCache,
+    verify_cache: bool,
+) -> anyhow::Result<FileManifest> {
     use crate::file_check::file_hash;
-    use tokio::fs;
+    use std::sync::Arc;
+    use tokio::{sync::Semaphore, task::JoinSet};
 
-    tracing::info!("folder: {}", folder.display());
+    tracing::info!(
+        "folder: {}, algorithm: {}, workers: {}",
+        folder.display(),
+        algorithm,
+        workers
+    );
     if !folder.is_dir() {
         return Err(anyhow::anyhow!("folder path not found"));
     }
 
-    let mut files = fs::read_dir(folder).await?;
-    let mut csv_content = String::new();
-    // spawn async tasks to calculate hash of each file
-    while let Ok(file) = files.next_entry().await {
-        if let Some(file) = file {
-            let file_path = file.path();
-            if file_path.is_dir() {
-                tracing::error!("skip folder: {}", file_path.display());
+    let mut manifest = FileManifest::new(algorithm);
+
+    // spawn async tasks to calculate hash of each file, at most `workers` files at the same time
+    let semaphore = Arc::new(Semaphore::new(workers.max(1)));
+    let mut tasks = JoinSet::new();
+    let mut cached = 0;
+    for (file_name, file_path) in list_files(folder, filter)? {
+        let (size, mtime_ns) = file_stamp(&file_path)?;
+        if !verify_cache {
+            if let Some(hash) = cache.get(&file_name, size, mtime_ns) {
+                manifest.insert(FileEntry {
+                    path: file_name,
+                    size,
+                    hash: hash.to_string(),
+                });
+                cached += 1;
                 continue;
             }
-            if file_path.extension().unwrap() != "bundle" {
-                continue;
+        }
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await?;
+            let hash = file_hash(&file_path, algorithm).await?;
+            anyhow::Ok((
+                FileEntry {
+                    path: file_name,
+                    size,
+                    hash,
+                },
+                mtime_ns,
+            ))
+        });
+    }
+
+    // the manifest is sorted by file name, keep the csv stable between builds
+    let mut stale = 0;
+    while let Some(result) = tasks.join_next().await {
+        let (entry, mtime_ns) = result??;
+        if let Some(cached_hash) = cache.get(&entry.path, entry.size, mtime_ns) {
+            if cached_hash != entry.hash {
+                tracing::warn!(
+                    "stale hash cache: {} cached {}, actual {}",
+                    entry.path,
+                    cached_hash,
+                    entry.hash
+                );
+                stale += 1;
             }
-            let file_name = file_path.file_name().unwrap().to_str().unwrap();
-            let file_hash = file_hash(&file_path).await?;
-            csv_content.push_str(&format!("{},{}\n", file_name, file_hash));
-        } else {
-            break;
         }
+        cache.insert(&entry.path, entry.size, mtime_ns, &entry.hash);
+        manifest.insert(entry);
     }
+    cache.retain(|path| manifest.entries.contains_key(path));
+
+    tracing::info!(
+        "hashed {} files, {} from cache{}",
+        manifest.entries.len(),
+        cached,
+        if verify_cache {
+            format!(", {} stale cache entries", stale)
+        } else {
+            String::new()
+        }
+    );
 
-    Ok(csv_content)
+    Ok(manifest)
 }
 
 mod test {
@@ -37,11 +161,138 @@ mod test {
     async fn test() {
         let folder = "C:/Github/unity-incremental-update/unity/ServerData/Android";
         let folder = std::path::Path::new(folder);
-        match super::folder_hash_list(folder).await {
+        match super::folder_hash_list(
+            folder,
+            &Default::default(),
+            Default::default(),
+            4,
+            &mut Default::default(),
+            false,
+        )
+        .await
+        {
             Ok(list) => {
-                tracing::info!("list: {:#}", list);
+                tracing::info!("list: {:#?}", list);
             }
             Err(e) => tracing::error!("error: {}", e),
         }
     }
+
+    #[tokio::test]
+    async fn test_sorted() {
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        for i in (0..20).rev() {
+            std::fs::write(folder.join(format!("{:02}.bundle", i)), [i as u8; 100]).unwrap();
+        }
+        std::fs::write(folder.join("skip.json"), "{}").unwrap();
+
+        let list = super::folder_hash_list(
+            folder,
+            &Default::default(),
+            Default::default(),
+            3,
+            &mut Default::default(),
+            false,
+        )
+        .await
+        .unwrap();
+        let names = list.entries.keys().cloned().collect::<Vec<_>>();
+        let expected = (0..20)
+            .map(|i| format!("{:02}.bundle", i))
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+    }
+
+    #[tokio::test]
+    async fn test_recursive_filter() {
+        use crate::giu_config::FileFilter;
+
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        std::fs::create_dir_all(folder.join("dlls/sub")).unwrap();
+        std::fs::write(folder.join("a.bundle"), "a").unwrap();
+        std::fs::write(folder.join("dlls/Game.dll.bytes"), "dll").unwrap();
+        std::fs::write(folder.join("dlls/sub/Debug.dll.bytes"), "debug").unwrap();
+        std::fs::write(folder.join("settings.json"), "{}").unwrap();
+        std::fs::write(folder.join("catalog_1.0.json"), "{}").unwrap();
+        std::fs::write(folder.join("NoExtension"), "x").unwrap();
+
+        let filter = FileFilter {
+            include: vec![
+                "**/*.bundle".to_string(),
+                "dlls/**/*.bytes".to_string(),
+                "*.json".to_string(),
+            ],
+            exclude: vec!["**/Debug.*".to_string()],
+        };
+        let list = super::folder_hash_list(
+            folder,
+            &filter,
+            Default::default(),
+            2,
+            &mut Default::default(),
+            false,
+        )
+        .await
+        .unwrap();
+        let names = list.entries.keys().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["a.bundle", "dlls/Game.dll.bytes", "settings.json"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        use crate::hash_cache::HashCache;
+
+        let folder = tempfile::tempdir().unwrap();
+        let folder = folder.path();
+        std::fs::write(folder.join("a.bundle"), "a").unwrap();
+
+        let mut cache = HashCache::default();
+        let first = super::folder_hash_list(
+            folder,
+            &Default::default(),
+            Default::default(),
+            2,
+            &mut cache,
+            false,
+        )
+        .await
+        .unwrap();
+        let (size, mtime_ns) = crate::hash_cache::file_stamp(&folder.join("a.bundle")).unwrap();
+        assert_eq!(
+            cache.get("a.bundle", size, mtime_ns),
+            Some(first.entries["a.bundle"].hash.as_str())
+        );
+
+        // a cached hash is reused while size and mtime are unchanged
+        cache.insert("a.bundle", size, mtime_ns, "STALE");
+        let cached = super::folder_hash_list(
+            folder,
+            &Default::default(),
+            Default::default(),
+            2,
+            &mut cache,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(cached.entries["a.bundle"].hash, "STALE");
+
+        // verify rehashes and fixes the stale entry
+        let verified = super::folder_hash_list(
+            folder,
+            &Default::default(),
+            Default::default(),
+            2,
+            &mut cache,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(verified.entries, first.entries);
+    }
 }
@@ -28,6 +28,7 @@ pub(crate) async fn is_git_repo_clean(folder: &Path) -> Result<bool> {
     Ok(is_clean.trim().is_empty())
 }
 
+#[allow(clippy::double_ended_iterator_last)]
 pub(crate) async fn get_git_tags(folder: &Path, loader_version: &str) -> Result<Vec<String>> {
     let output = process::Command::new("git")
         .current_dir(folder)
@@ -72,30 +73,45 @@ pub(crate) async fn export_file_in_git_by_tag(
     tag: &str,
     file: &str,
     des_folder: &Path,
+) -> Result<()> {
+    export_files_in_git_by_tag(folder, tag, &[file], des_folder).await
+}
+
+pub(crate) async fn export_files_in_git_by_tag(
+    folder: &Path,
+    tag: &str,
+    files: &[&str],
+    des_folder: &Path,
 ) -> Result<()> {
     fs::create_dir_all(des_folder)?;
     let des_file_path = des_folder.join("archive.zip");
     let des_file = des_file_path.to_str().unwrap();
     let des_file = win_to_cyg(des_file);
-    let file = win_to_cyg(file);
+    let files = files.iter().map(|f| win_to_cyg(f)).collect::<Vec<_>>();
     tracing::info!(
         "git archive --format=zip --output={} {} {}",
         des_file,
         tag,
-        file
+        files.join(" ")
     );
     // git archive
-    let _ = process::Command::new("git")
+    let output = process::Command::new("git")
         .current_dir(folder)
         .args([
             "archive",
             "--format=zip",
             &format!("--output={}", des_file),
             tag,
-            &file,
         ])
+        .args(&files)
         .output()
         .await?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git archive failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
     // unzip
     file_zip::extract(&des_folder.join("archive.zip"), des_folder)?;
 
@@ -1,7 +1,210 @@
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use crate::bundle_transform::BundleTransform;
+use crate::file_check::HashAlgorithm;
+use crate::file_zip::CompressionMethod;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct GIUConfig {
     pub unity_path: String,
     pub platforms: Vec<String>,
+    // store changed bundles as binary deltas against the base tag when smaller
+    #[serde(default)]
+    pub delta_patch: bool,
+    // bundles larger than this, in MB, are never delta encoded, the encoder holds both versions in memory
+    #[serde(default = "default_delta_max_size_mb")]
+    pub delta_max_size_mb: u64,
+    // number of bundles delta encoded at the same time, across all the tag patches
+    #[serde(default = "default_delta_workers")]
+    pub delta_workers: usize,
+    // number of files hashed at the same time
+    #[serde(default = "default_workers")]
+    pub hash_workers: usize,
+    // number of tag patches generated at the same time
+    #[serde(default = "default_workers")]
+    pub patch_workers: usize,
+    // crc32, sha256, blake3 or xxh3
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    // skip the incremental patch larger than this ratio of the full package, use the full package
+    #[serde(default = "default_max_patch_ratio")]
+    pub max_patch_ratio: f64,
+    // which tags receive incremental patches
+    #[serde(default)]
+    pub tag_policy: TagPolicy,
+    // files of ServerData/<platform> to hash and patch
+    #[serde(default)]
+    pub filter: FileFilter,
+    // platform specific filters, replace `filter` for the platform
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub platform_filter: HashMap<String, FileFilter>,
+    // obfuscation of the bundles of each platform after the Unity build, none if not set
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub bundle_transform: HashMap<String, BundleTransform>,
+    // days the clients accept an update_info after it is issued
+    #[serde(default = "default_update_info_expiry_days")]
+    pub update_info_expiry_days: u64,
+    // also write the vers / downs / sizes lists of update_info read by the older clients
+    #[serde(default = "default_legacy_update_info")]
+    pub legacy_update_info: bool,
+    // compression of the zip entries, the first matching rule wins, Deflate otherwise
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compression: Vec<CompressionRule>,
+    // Ed25519 private key signing update_info and the packages, unsigned if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<KeySource>,
+    // AES-256-GCM keys of the packages, not encrypted if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionConfig>,
+}
+
+/// Package encryption keys by id, the older keys still decrypt the packages published with them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct EncryptionConfig {
+    // id of the key encrypting the new packages, recorded in update_info
+    pub key_id: String,
+    pub keys: BTreeMap<String, KeySource>,
+}
+
+/// A 32 bytes key as 64 hex characters
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct KeySource {
+    // file holding the key, relative to the project folder
+    #[serde(default)]
+    pub key_file: Option<String>,
+    // env var holding the key, wins over `key_file`
+    #[serde(default)]
+    pub key_env: Option<String>,
+}
+
+/// Compression of the zip entries matching the glob pattern
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct CompressionRule {
+    pub pattern: String,
+    // stored, deflate, zstd, bzip2 or auto
+    pub method: CompressionMethod,
+    // level of the method, the default of the method if not set
+    #[serde(default)]
+    pub level: Option<i64>,
+}
+
+/// Direct patches for the `recent` latest tags and every checkpoint version,
+/// older tags chain through the next checkpoint
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub(crate) struct TagPolicy {
+    // number of latest tags patched directly, 0 patches every tag directly
+    #[serde(default)]
+    pub recent: usize,
+    // every patch version divisible by it is a checkpoint, 0 disables checkpoints
+    #[serde(default)]
+    pub checkpoint_interval: u32,
+}
+
+/// Glob patterns relative to the platform folder, e.g. `**/*.bundle`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct FileFilter {
+    #[serde(default = "default_include")]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+fn default_include() -> Vec<String> {
+    vec!["**/*.bundle".to_string()]
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        Self {
+            include: default_include(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl KeySource {
+    // the key file is relative to the project folder, the env var wins when both are set
+    pub fn load(&self, project_path: &Path) -> Result<Option<[u8; 32]>> {
+        let hex_key = if let Some(key_env) = self.key_env.as_ref() {
+            std::env::var(key_env)
+                .map_err(|_| anyhow::anyhow!("Key env var {} not set", key_env))?
+        } else if let Some(key_file) = self.key_file.as_ref() {
+            std::fs::read_to_string(project_path.join(key_file))?
+        } else {
+            return Ok(None);
+        };
+        let key = hex::decode(hex_key.trim())?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Key must be 32 bytes, 64 hex characters"))?;
+        Ok(Some(key))
+    }
+}
+
+impl GIUConfig {
+    pub fn file_filter(&self, platform: &str) -> &FileFilter {
+        self.platform_filter.get(platform).unwrap_or(&self.filter)
+    }
+
+    pub fn bundle_transform(&self, platform: &str) -> BundleTransform {
+        self.bundle_transform
+            .get(platform)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn default_delta_max_size_mb() -> u64 {
+    64
+}
+
+fn default_delta_workers() -> usize {
+    2
+}
+
+fn default_max_patch_ratio() -> f64 {
+    1.0
+}
+
+fn default_update_info_expiry_days() -> u64 {
+    30
+}
+
+fn default_legacy_update_info() -> bool {
+    true
+}
+
+fn default_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+impl Default for GIUConfig {
+    fn default() -> Self {
+        Self {
+            unity_path: "/path/to/unity".to_string(),
+            platforms: vec!["Android".to_string(), "iOS".to_string()],
+            delta_patch: false,
+            delta_max_size_mb: default_delta_max_size_mb(),
+            delta_workers: default_delta_workers(),
+            hash_workers: default_workers(),
+            patch_workers: default_workers(),
+            hash_algorithm: HashAlgorithm::default(),
+            max_patch_ratio: default_max_patch_ratio(),
+            tag_policy: TagPolicy::default(),
+            filter: FileFilter::default(),
+            platform_filter: HashMap::new(),
+            bundle_transform: HashMap::new(),
+            update_info_expiry_days: default_update_info_expiry_days(),
+            legacy_update_info: default_legacy_update_info(),
+            compression: Vec::new(),
+            signing: None,
+            encryption: None,
+        }
+    }
 }
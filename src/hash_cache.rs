@@ -0,0 +1,136 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::file_check::HashAlgorithm;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct CacheEntry {
+    size: u64,
+    mtime_ns: u64,
+    hash: String,
+}
+
+/// Persistent file hashes keyed by relative path, reused while size and mtime are unchanged
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct HashCache {
+    algorithm: HashAlgorithm,
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+/// Size and modification time of a file, in nanoseconds since the unix epoch
+pub(crate) fn file_stamp(path: &Path) -> Result<(u64, u64)> {
+    let meta = fs::metadata(path)?;
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH)?;
+    Ok((meta.len(), mtime.as_nanos() as u64))
+}
+
+impl HashCache {
+    // unity-project-folder/Library/GIUHashCache/platform.json
+    pub fn path(project_path: &Path, platform: &str) -> PathBuf {
+        project_path
+            .join("Library")
+            .join("GIUHashCache")
+            .join(format!("{}.json", platform))
+    }
+
+    // unity-project-folder/Library/GIUHashCache/platform.sha256.json,
+    // the store keys of the files when `hash_algorithm` is not sha256
+    pub fn store_path(project_path: &Path, platform: &str) -> PathBuf {
+        project_path
+            .join("Library")
+            .join("GIUHashCache")
+            .join(format!("{}.sha256.json", platform))
+    }
+
+    // a missing or broken cache, or one of another algorithm, is empty
+    pub fn load(file: &Path, algorithm: HashAlgorithm) -> Self {
+        let empty = Self {
+            algorithm,
+            entries: BTreeMap::new(),
+        };
+        if !file.is_file() {
+            return empty;
+        }
+        let cache = fs::read_to_string(file)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(serde_json::from_str::<HashCache>(&content)?));
+        match cache {
+            Ok(cache) if cache.algorithm == algorithm => cache,
+            Ok(cache) => {
+                tracing::info!(
+                    "hash cache {} is {}, ignored",
+                    file.display(),
+                    cache.algorithm
+                );
+                empty
+            }
+            Err(e) => {
+                tracing::warn!("hash cache {} ignored: {}", file.display(), e);
+                empty
+            }
+        }
+    }
+
+    pub fn save(&self, file: &Path) -> Result<()> {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(file, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, path: &str, size: u64, mtime_ns: u64) -> Option<&str> {
+        self.entries
+            .get(path)
+            .filter(|entry| entry.size == size && entry.mtime_ns == mtime_ns)
+            .map(|entry| entry.hash.as_str())
+    }
+
+    pub fn insert(&mut self, path: &str, size: u64, mtime_ns: u64, hash: &str) {
+        self.entries.insert(
+            path.to_string(),
+            CacheEntry {
+                size,
+                mtime_ns,
+                hash: hash.to_string(),
+            },
+        );
+    }
+
+    // drop the entries of files which no longer exist
+    pub fn retain(&mut self, paths: impl Fn(&str) -> bool) {
+        self.entries.retain(|path, _| paths(path));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let folder = tempfile::tempdir().unwrap();
+        let file = folder.path().join("Android.json");
+
+        let mut cache = HashCache::load(&file, HashAlgorithm::Sha256);
+        assert!(cache.get("a.bundle", 1, 2).is_none());
+        cache.insert("a.bundle", 1, 2, "aa");
+        cache.insert("b.bundle", 3, 4, "bb");
+        cache.retain(|path| path != "b.bundle");
+        cache.save(&file).unwrap();
+
+        let cache = HashCache::load(&file, HashAlgorithm::Sha256);
+        assert_eq!(cache.get("a.bundle", 1, 2), Some("aa"));
+        assert!(cache.get("a.bundle", 1, 3).is_none());
+        assert!(cache.get("b.bundle", 3, 4).is_none());
+
+        let cache = HashCache::load(&file, HashAlgorithm::Crc32);
+        assert!(cache.get("a.bundle", 1, 2).is_none());
+    }
+}
@@ -1,23 +1,50 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use ed25519_dalek::SigningKey;
 use folder_hash_list::folder_hash_list;
 use giu_config::GIUConfig;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::Instrument;
 
+mod artifact_store;
+mod bundle_transform;
+use artifact_store::ArtifactStore;
 mod file_check;
+use file_check::{file_hash, reader_hash, HashAlgorithm};
+mod file_manifest;
+use file_manifest::{csv_records, package_extra_files, FileManifest};
+mod file_delta;
+mod file_diff;
+use file_diff::{diff_file_hash_map, FileDiff};
+mod tag_policy;
+use tag_policy::{plan_tags, tag_patch_version};
 mod run_unity_build;
 use run_unity_build::run_unity_build;
 mod win_cyg;
 use win_cyg::cyg_to_win;
 mod git_cmd;
 use git_cmd::{
-    export_file_in_git_by_tag, get_git_tags, git_commit_with_tag, is_git_repo, is_git_repo_clean,
+    export_file_in_git_by_tag, export_files_in_git_by_tag, get_git_tags, git_commit_with_tag,
+    is_git_repo, is_git_repo_clean,
 };
 mod file_zip;
+use file_zip::{CompressionPolicy, EntryCache, PackageKeys};
 mod folder_hash_list;
 mod giu_config;
+mod hash_cache;
+use hash_cache::HashCache;
 mod log_util;
+mod patch_simulate;
+mod patch_verify;
+mod signing;
+mod update_info;
+use update_info::{format_size, unix_now, PackageInfo, ReleaseStamp, UpdateInfo};
 
 /// Generate incremental updates via Git Tags
 #[derive(Parser, Debug)]
@@ -26,96 +53,475 @@ struct Args {
     /// Unity Project Folder Path
     #[arg(short, long)]
     project_path: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Platforms a stage runs for
+#[derive(clap::Args, Debug)]
+struct PlatformArgs {
+    /// Platform to process, repeat it for several, every configured platform if not set
+    #[arg(long = "platform", value_name = "PLATFORM")]
+    platforms: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a default .giu_config.toml in the project folder
+    Init,
+    /// Build, hash and pack every platform, then commit and tag the new Game Patch Version
+    Run {
+        /// Rehash every file and report the stale entries of the hash cache
+        #[arg(long)]
+        verify_cache: bool,
+    },
+    /// Build the Addressables of the platforms with Unity
+    Build {
+        #[command(flatten)]
+        platforms: PlatformArgs,
+    },
+    /// Transform and hash the built files, and keep them in the store
+    Hash {
+        #[command(flatten)]
+        platforms: PlatformArgs,
+        /// Rehash every file and report the stale entries of the hash cache
+        #[arg(long)]
+        verify_cache: bool,
+    },
+    /// Report the files changed and removed since each tag, from the hashed files
+    Diff {
+        #[command(flatten)]
+        platforms: PlatformArgs,
+    },
+    /// Zip the hashed files into the packages, then write, verify and sign update_info
+    Pack {
+        #[command(flatten)]
+        platforms: PlatformArgs,
+    },
+    /// Commit the packed Game Patch Version and tag it
+    Release,
+    /// Issue the served update_info again with a new release counter and expiry, and sign it, the packages are left as they are
+    Restamp {
+        #[command(flatten)]
+        platforms: PlatformArgs,
+    },
+    /// Check the packages against the build, and their signatures when a public key is given
+    Verify {
+        #[command(flatten)]
+        platforms: PlatformArgs,
+        /// Public key, hex or a file holding it
+        #[arg(long, value_name = "PUBLIC_KEY")]
+        public_key: Option<String>,
+    },
+    /// Apply every package to the files of its base version and compare with ServerData
+    Simulate {
+        #[command(flatten)]
+        platforms: PlatformArgs,
+    },
+    /// List the tags of the loader version and the packages of each platform
+    List,
+    /// Restore the stored files of a published Game Patch Version
+    Restore {
+        #[arg(value_name = "PATCH_VERSION")]
+        patch_version: u32,
+    },
+}
+
+fn load_file_hash_map(file: &Path) -> Result<(HashAlgorithm, HashMap<String, String>)> {
+    let manifest = FileManifest::load(file)?;
+    Ok((manifest.algorithm, manifest.hash_map()))
 }
 
-fn load_file_hash_map(file: &Path) -> Result<HashMap<String, String>> {
-    let file_content = fs::read_to_string(file)?;
+// hash the stored files of a tag with another algorithm, so that the file hash lists can be compared,
+// the hash of a file missing in the store is left empty and the file is treated as changed
+async fn rehash_stored_files(
+    store: &ArtifactStore,
+    tag: &str,
+    file_hash_map: &HashMap<String, String>,
+    algorithm: HashAlgorithm,
+) -> Result<HashMap<String, String>> {
+    let tag_objects = store
+        .release_objects(tag_patch_version(tag))?
+        .unwrap_or_default();
     let mut map = HashMap::new();
-    for line in file_content.lines() {
-        let parts: Vec<&str> = line.split(',').collect();
-        if parts.len() != 2 {
-            continue;
-        }
-        let file_name = parts[0].trim();
-        let hash = parts[1].trim();
-        map.insert(file_name.to_string(), hash.to_string());
+    for file_name in file_hash_map.keys() {
+        let object = tag_objects.get(file_name).and_then(|hash| store.get(hash));
+        let hash = match object {
+            Some(object) => file_hash(&object, algorithm).await?,
+            None => String::new(),
+        };
+        map.insert(file_name.to_string(), hash);
     }
-
     Ok(map)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct PatchInfo {
-    pub ver: String,
-    pub down: String,
-    pub size: String,
-}
+// export the file hash list of the tag into the tag folder and diff it with the current build,
+// returns the file hash list of the tag and the difference
+#[allow(clippy::too_many_arguments)]
+async fn diff_tag(
+    project_path: &Path,
+    platform: &str,
+    hash_file: &Path,
+    hash_algorithm: HashAlgorithm,
+    new_file_hash_map: &HashMap<String, String>,
+    store: &ArtifactStore,
+    tag: &str,
+    tag_folder: &Path,
+) -> Result<(HashMap<String, String>, FileDiff)> {
+    fs::create_dir_all(tag_folder)?;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct PlatformPatchInfo {
-    // latest version
-    pub ver: String,
-    // latest version full package download name
-    pub down: String,
-    pub size: String,
+    export_file_in_git_by_tag(project_path, tag, hash_file.to_str().unwrap(), tag_folder).await?;
 
-    // incremental package versions
-    pub vers: Vec<String>,
-    // incremental package download name match with versions
-    pub downs: Vec<String>,
-    // incremental package sizes match with versions
-    pub sizes: Vec<String>,
+    // the file in archive.zip is relative to the project folder
+    let (tag_hash_algorithm, tag_file_hash_map) = load_file_hash_map(
+        &tag_folder
+            .join("ServerData")
+            .join(platform)
+            .join("file-hash.csv"),
+    )?;
+
+    let file_diff = if tag_hash_algorithm == hash_algorithm {
+        diff_file_hash_map(&tag_file_hash_map, new_file_hash_map)
+    } else {
+        tracing::warn!(
+            "Tag: {} hashed with {}, rehash stored files with {}",
+            tag,
+            tag_hash_algorithm,
+            hash_algorithm
+        );
+        let tag_file_hash_map =
+            rehash_stored_files(store, tag, &tag_file_hash_map, hash_algorithm).await?;
+        diff_file_hash_map(&tag_file_hash_map, new_file_hash_map)
+    };
+    Ok((tag_file_hash_map, file_diff))
 }
 
-async fn generate_incremental_updates() -> Result<()> {
-    let args: Args = Args::parse();
-    let project_path = cyg_to_win(&args.project_path);
-    let project_path = std::path::absolute(Path::new(&project_path))?;
-    println!("project_path: {:?}", project_path);
+// read the bundles of the tag from the store, or export them from git,
+// and write a delta for each one that is smaller than the new file,
+// returns the names of the bundles stored as deltas
+#[allow(clippy::too_many_arguments)]
+async fn generate_bundle_deltas(
+    project_path: &Path,
+    platform_folder: &Path,
+    tag: &str,
+    file_names: &[&String],
+    store: &ArtifactStore,
+    tag_folder: &Path,
+    delta_folder: &Path,
+    delta_limit: &DeltaLimit,
+) -> Result<HashSet<String>> {
+    let mut delta_files = HashSet::new();
+    if file_names.is_empty() {
+        return Ok(delta_files);
+    }
 
-    if !project_path.is_dir() {
-        return Err(anyhow::anyhow!("Invalid project path"));
+    // the file in archive.zip is relative to the project folder
+    let tag_platform_folder = tag_folder.join(platform_folder.strip_prefix(project_path)?);
+
+    let tag_objects = store
+        .release_objects(tag_patch_version(tag))?
+        .unwrap_or_default();
+    let mut old_files = HashMap::new();
+    let mut tag_files = Vec::new();
+    for name in file_names.iter() {
+        match tag_objects.get(*name).and_then(|hash| store.get(hash)) {
+            Some(object) => {
+                old_files.insert(name.to_string(), object);
+            }
+            None => {
+                tag_files.push(platform_folder.join(name).to_str().unwrap().to_string());
+                old_files.insert(name.to_string(), tag_platform_folder.join(name));
+            }
+        }
+    }
+    if !tag_files.is_empty() {
+        let tag_files = tag_files.iter().map(|f| f.as_str()).collect::<Vec<_>>();
+        if let Err(e) = export_files_in_git_by_tag(project_path, tag, &tag_files, tag_folder).await
+        {
+            tracing::warn!("Can not export bundles of tag: {}, {}", tag, e);
+        }
     }
 
-    let _guards = log_util::init(&project_path);
+    fs::create_dir_all(delta_folder)?;
+    for name in file_names.iter() {
+        let old_file = &old_files[*name];
+        let new_file = platform_folder.join(name);
+        if !old_file.is_file() {
+            continue;
+        }
+        let old_size = fs::metadata(old_file)?.len();
+        let new_size = fs::metadata(&new_file)?.len();
+        if old_size.max(new_size) > delta_limit.max_size {
+            tracing::info!(
+                "delta {}: {} bytes, above the delta limit, full",
+                name,
+                old_size.max(new_size)
+            );
+            continue;
+        }
+        // both versions, the delta and the rebuilt bundle are in memory, bound how many at a time
+        let _permit = delta_limit.workers.acquire().await?;
+        let old = fs::read(old_file)?;
+        let new = fs::read(&new_file)?;
+        let delta = file_delta::encode(&old, &new);
+        let new_hash = reader_hash(&mut new.as_slice(), HashAlgorithm::Sha256)?;
+        drop(new);
+        // make sure the client can rebuild the bundle from the delta
+        let rebuilt = file_delta::apply(&old, &delta)?;
+        drop(old);
+        if reader_hash(&mut rebuilt.as_slice(), HashAlgorithm::Sha256)? != new_hash {
+            return Err(anyhow::anyhow!("Invalid delta generated for: {}", name));
+        }
+        drop(rebuilt);
+        tracing::info!(
+            "delta {}: {} bytes, full: {} bytes",
+            name,
+            delta.len(),
+            new_size
+        );
+        if (delta.len() as u64) < new_size {
+            let delta_file = delta_folder.join(format!("{}.delta", name));
+            fs::create_dir_all(delta_file.parent().unwrap())?;
+            fs::write(delta_file, delta)?;
+            delta_files.insert(name.to_string());
+        }
+    }
 
-    if !is_git_repo(&project_path).await? {
-        return Err(anyhow::anyhow!("project folder is not in Git repository"));
+    Ok(delta_files)
+}
+
+/// Memory bound of the delta encoding, shared by the patch tasks of every platform
+struct DeltaLimit {
+    // bytes, larger bundles are patched in full
+    max_size: u64,
+    workers: Semaphore,
+}
+
+/// Shared state of the patch tasks of a platform
+struct TagPatchContext {
+    project_path: PathBuf,
+    platform: String,
+    platform_folder: PathBuf,
+    platform_patches_path: PathBuf,
+    loader_version: String,
+    patch_version: u32,
+    is_checkpoint: bool,
+    hash_file: PathBuf,
+    hash_algorithm: HashAlgorithm,
+    new_file_hash_map: HashMap<String, String>,
+    store: ArtifactStore,
+    delta_patch: bool,
+    compression_policy: CompressionPolicy,
+    entry_cache_file: PathBuf,
+    full_patch_file_name: String,
+    full_file_bytes: u64,
+    max_patch_ratio: f64,
+    package_keys: PackageKeys,
+    delta_limit: Arc<DeltaLimit>,
+}
+
+// export the file hash list of the tag, diff it with the current build and zip the patch,
+// returns the package of the tag, the full package when the patch is not worth it
+async fn generate_tag_patch(context: &TagPatchContext, tag: &str) -> Result<String> {
+    let project_path = &context.project_path;
+    let platform_folder = &context.platform_folder;
+    let platform_patches_path = &context.platform_patches_path;
+    let new_file_hash_map = &context.new_file_hash_map;
+    let hash_algorithm = context.hash_algorithm;
+
+    tracing::info!(
+        "Generating incremental updates for platform: {} patch for: {}",
+        context.platform,
+        tag
+    );
+    let tag_folder = platform_patches_path.join(tag);
+    let (tag_file_hash_map, file_diff) = diff_tag(
+        project_path,
+        &context.platform,
+        &context.hash_file,
+        hash_algorithm,
+        new_file_hash_map,
+        &context.store,
+        tag,
+        &tag_folder,
+    )
+    .await?;
+
+    // bundles which existed in the tag can be stored as deltas
+    let delta_folder = tag_folder.join("delta");
+    if delta_folder.is_dir() {
+        fs::remove_dir_all(&delta_folder)?;
     }
+    let delta_files = if context.delta_patch {
+        let changed_in_tag = file_diff
+            .changed
+            .iter()
+            .filter(|name| tag_file_hash_map.contains_key(*name))
+            .collect::<Vec<_>>();
+        generate_bundle_deltas(
+            project_path,
+            platform_folder,
+            tag,
+            &changed_in_tag,
+            &context.store,
+            &tag_folder,
+            &delta_folder,
+            &context.delta_limit,
+        )
+        .await?
+    } else {
+        HashSet::new()
+    };
 
-    if !is_git_repo_clean(&project_path).await? {
-        return Err(anyhow::anyhow!("project folder has uncommitted changes"));
+    let mut diff_files = Vec::new();
+    let mut delta_entries = Vec::new();
+    let mut diff_file_list = Vec::new();
+    for file_name in file_diff.changed.iter() {
+        let kind = if delta_files.contains(file_name) {
+            delta_entries.push(format!("{}.delta", file_name));
+            "delta"
+        } else {
+            diff_files.push(file_name);
+            "full"
+        };
+        diff_file_list.push([
+            file_name.as_str(),
+            new_file_hash_map[file_name].as_str(),
+            kind,
+        ]);
     }
 
+    let diff_file_list_name = format!("diff-{}.csv", tag);
+    let diff_file = platform_folder.join(&diff_file_list_name);
+    fs::write(&diff_file, csv_records(diff_file_list)?)?;
+    diff_files.push(&diff_file_list_name);
+
+    // files removed since the tag, the client should delete them
+    let remove_file_list = file_diff
+        .removed
+        .iter()
+        .map(|file_name| [file_name.as_str(), tag_file_hash_map[file_name].as_str()]);
+    tracing::info!(
+        "Tag: {} changed files: {}, removed files: {}",
+        tag,
+        file_diff.changed.len(),
+        file_diff.removed.len()
+    );
+
+    let remove_file_list_name = format!("remove-{}.csv", tag);
+    let remove_file = platform_folder.join(&remove_file_list_name);
+    fs::write(&remove_file, csv_records(remove_file_list)?)?;
+    diff_files.push(&remove_file_list_name);
+
+    // copy catalog, catalog hash and version file
+    let extra_files = package_extra_files(&context.loader_version);
+    diff_files.extend(extra_files.iter());
+
+    let patch_file = platform_patches_path.join(format!("{}.zip", tag));
+    // zipping blocks, let the runtime move the other tasks off this thread
+    tokio::task::block_in_place(|| {
+        let mut entry_cache = EntryCache::open(&context.entry_cache_file)?;
+        file_zip::compress_with_cache(
+            platform_folder,
+            &diff_files,
+            &patch_file,
+            false,
+            &context.compression_policy,
+            &mut entry_cache,
+        )?;
+        if !delta_entries.is_empty() {
+            let delta_entries = delta_entries.iter().collect::<Vec<_>>();
+            file_zip::compress(
+                &delta_folder,
+                &delta_entries,
+                &patch_file,
+                true,
+                &context.compression_policy,
+            )?;
+        }
+        anyhow::Ok(())
+    })?;
+    tracing::info!("Generated patch for tag: {}", tag);
+
+    let file_bytes = fs::metadata(&patch_file)?.len();
+    let down = if file_bytes as f64 > context.full_file_bytes as f64 * context.max_patch_ratio {
+        // not worth it, the full package replaces all the files
+        tracing::info!(
+            "Patch for tag: {} is {} bytes, full package is {} bytes, use full package",
+            tag,
+            file_bytes,
+            context.full_file_bytes
+        );
+        fs::remove_file(&patch_file)?;
+        context.full_patch_file_name.to_string()
+    } else {
+        if let Some(key) = context.package_keys.current.as_ref() {
+            file_zip::encrypt_package(&patch_file, key)?;
+        }
+        if context.is_checkpoint {
+            // keep the patch for the clients chaining through this checkpoint later
+            let checkpoint_folder =
+                platform_patches_path.join(checkpoint_folder_name(context.patch_version));
+            fs::create_dir_all(&checkpoint_folder)?;
+            fs::copy(&patch_file, checkpoint_folder.join(format!("{}.zip", tag)))?;
+        }
+        format!("{}.zip", tag)
+    };
+    Ok(down)
+}
+
+// folder keeping the patches of a checkpoint version, the latest patches are overwritten every release
+fn checkpoint_folder_name(patch_version: u32) -> String {
+    format!("checkpoint-{}", patch_version)
+}
+
+fn load_giu_config(project_path: &Path) -> Result<GIUConfig> {
     let giu_config = project_path.join(".giu_config.toml");
     if !giu_config.is_file() {
-        // create default giu_config
-        let default_giu_config = GIUConfig {
-            unity_path: "/path/to/unity".to_string(),
-            platforms: vec!["Android".to_string(), "iOS".to_string()],
-        };
-        let default_giu_config_str = toml::to_string(&default_giu_config)?;
-        fs::write(&giu_config, default_giu_config_str)?;
         return Err(anyhow::anyhow!(
-            "No .giu_config file found, created default one"
+            "No .giu_config.toml found, run init to create a default one"
         ));
     }
     let giu_config_content = fs::read_to_string(giu_config)?;
     let giu_config: GIUConfig = toml::from_str(&giu_config_content)?;
+    Ok(giu_config)
+}
 
-    let unity_path = cyg_to_win(&giu_config.unity_path);
-    let unity_path = Path::new(&unity_path);
-    if !unity_path.is_file() {
-        return Err(anyhow::anyhow!(
-            "Unity path not configured in .giu_config.toml"
-        ));
+// create the default giu_config
+fn init_giu_config(project_path: &Path) -> Result<()> {
+    let giu_config = project_path.join(".giu_config.toml");
+    if giu_config.is_file() {
+        return Err(anyhow::anyhow!("{} already exists", giu_config.display()));
     }
+    fs::write(&giu_config, toml::to_string(&GIUConfig::default())?)?;
+    tracing::info!(
+        "Created {}, set unity_path and platforms",
+        giu_config.display()
+    );
+    Ok(())
+}
 
-    let platforms = giu_config.platforms;
-    if platforms.is_empty() {
+// every configured platform, or the requested ones
+fn select_platforms(giu_config: &GIUConfig, args: &PlatformArgs) -> Result<Vec<String>> {
+    if giu_config.platforms.is_empty() {
         return Err(anyhow::anyhow!("No platforms found in .giu_config.toml"));
     }
+    if args.platforms.is_empty() {
+        return Ok(giu_config.platforms.clone());
+    }
+    for platform in args.platforms.iter() {
+        if !giu_config.platforms.contains(platform) {
+            return Err(anyhow::anyhow!(
+                "Platform {} not configured in .giu_config.toml",
+                platform
+            ));
+        }
+    }
+    Ok(args.platforms.clone())
+}
 
+fn load_loader_version(project_path: &Path) -> Result<String> {
     let loader_version = project_path
         .join("Assets")
         .join("Resources")
@@ -126,15 +532,554 @@ async fn generate_incremental_updates() -> Result<()> {
         ));
     }
     let loader_version = fs::read_to_string(loader_version)?.trim().to_string();
+    Ok(loader_version)
+}
+
+// unity-project-folder/../host/serve/loader-version
+fn serve_path(project_path: &Path, loader_version: &str) -> PathBuf {
+    project_path
+        .parent()
+        .unwrap()
+        .join("host")
+        .join("serve")
+        .join(loader_version)
+}
+
+// host/serve/release_counter keeps the counter across the loader versions,
+// the next release is above it and above every update_info of the loader version
+fn next_release_stamp(
+    patches_path: &Path,
+    loader_version: &str,
+    platforms: &[String],
+    expiry_days: u64,
+) -> Result<ReleaseStamp> {
+    let counter_file = patches_path.parent().unwrap().join("release_counter");
+    let mut release = if counter_file.is_file() {
+        fs::read_to_string(&counter_file)?.trim().parse::<u64>()?
+    } else {
+        0
+    };
+    for platform in platforms.iter() {
+        if let Ok(update_info) = UpdateInfo::load(&patches_path.join(platform).join("update_info"))
+        {
+            release = release.max(update_info.stamp.release);
+        }
+    }
+    release += 1;
+    // a failed run leaves a gap, never a counter used twice
+    fs::write(&counter_file, release.to_string())?;
+
+    let issued = unix_now()?;
+    Ok(ReleaseStamp {
+        loader_version: loader_version.to_string(),
+        release,
+        issued,
+        expires: issued + expiry_days * 24 * 60 * 60,
+    })
+}
+
+// rebuild the files of a published version of each platform from the store
+fn restore_from_store(project_path: &Path, patch_version: u32) -> Result<()> {
+    let giu_config = load_giu_config(project_path)?;
+    let loader_version = load_loader_version(project_path)?;
+
+    for platform in giu_config.platforms.iter() {
+        let store = ArtifactStore::new(project_path, &loader_version, platform);
+        let file_hash_map = store.release_objects(patch_version)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Version {} of {} not found in store",
+                patch_version,
+                platform
+            )
+        })?;
+        let des_folder = store.restore_path(patch_version);
+        store.restore(&file_hash_map, &des_folder)?;
+        tracing::info!(
+            "Restored {} version {} to {}",
+            platform,
+            patch_version,
+            des_folder.display()
+        );
+    }
+
+    Ok(())
+}
+
+// simulate the clients of each platform updating with the generated packages
+async fn simulate_patches(project_path: &Path, platforms: &PlatformArgs) -> Result<()> {
+    let giu_config = load_giu_config(project_path)?;
+    let loader_version = load_loader_version(project_path)?;
+    let patches_path = serve_path(project_path, &loader_version);
+    let package_keys = PackageKeys::load(giu_config.encryption.as_ref(), project_path)?;
+
+    for platform in select_platforms(&giu_config, platforms)?.iter() {
+        patch_simulate::simulate_platform(
+            project_path,
+            &loader_version,
+            platform,
+            giu_config.file_filter(platform),
+            &patches_path,
+            &package_keys,
+        )
+        .await?;
+        tracing::info!("Simulated updates of {} match the build", platform);
+    }
+
+    Ok(())
+}
+
+// check the packages of each platform against the build,
+// and the detached signatures of update_info and every package it lists
+fn verify_packages(
+    project_path: &Path,
+    platforms: &PlatformArgs,
+    public_key: Option<&str>,
+) -> Result<()> {
+    let giu_config = load_giu_config(project_path)?;
+    let loader_version = load_loader_version(project_path)?;
+    let patches_path = serve_path(project_path, &loader_version);
+    let package_keys = PackageKeys::load(giu_config.encryption.as_ref(), project_path)?;
+    let public_key = public_key.map(signing::load_verifying_key).transpose()?;
+
+    for platform in select_platforms(&giu_config, platforms)?.iter() {
+        let platform_patches_path = patches_path.join(platform);
+        patch_verify::verify_platform_patches(
+            &project_path.join("ServerData").join(platform),
+            &platform_patches_path,
+            &loader_version,
+            &package_keys,
+            &ArtifactStore::new(project_path, &loader_version, platform),
+        )?;
+
+        let Some(public_key) = public_key.as_ref() else {
+            continue;
+        };
+        let update_info_file = platform_patches_path.join("update_info");
+        signing::verify_file(public_key, &update_info_file)?;
+        let update_info = UpdateInfo::load(&update_info_file)?;
+        for package in update_info.packages() {
+            signing::verify_file(public_key, &platform_patches_path.join(&package.file))?;
+        }
+        tracing::info!("Signatures of {} verified", platform);
+    }
+
+    Ok(())
+}
+
+// the tags of the loader version and what update_info of each platform serves
+async fn list_release(project_path: &Path) -> Result<()> {
+    let giu_config = load_giu_config(project_path)?;
+    let loader_version = load_loader_version(project_path)?;
+    let patches_path = serve_path(project_path, &loader_version);
+
+    let tags = get_git_tags(project_path, &loader_version).await?;
+    tracing::info!("Loader version: {}, {} tags", loader_version, tags.len());
+    for tag in tags.iter() {
+        let tag_info = git_cmd::get_git_tag_info(project_path, tag).await?;
+        tracing::info!("    {}", tag_info);
+    }
+
+    for platform in giu_config.platforms.iter() {
+        let update_info_file = patches_path.join(platform).join("update_info");
+        if !update_info_file.is_file() {
+            tracing::info!("{}: not packed", platform);
+            continue;
+        }
+        let update_info = UpdateInfo::load(&update_info_file)?;
+        tracing::info!(
+            "{}: version {}, release {}, expires {}",
+            platform,
+            update_info.version,
+            update_info.stamp.release,
+            time::OffsetDateTime::from_unix_timestamp(update_info.stamp.expires as i64)?
+                .format(&time::format_description::well_known::Rfc3339)?
+        );
+        for package in update_info.packages() {
+            tracing::info!(
+                "    {} -> {}: {}, {}",
+                package.from.as_deref().unwrap_or("install"),
+                package.to,
+                package.file,
+                format_size(package.size)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Settings and versions shared by the packaging of every platform of a release
+struct ReleaseContext {
+    project_path: PathBuf,
+    giu_config: GIUConfig,
+    compression_policy: CompressionPolicy,
+    loader_version: String,
+    // tags of the loader version, latest first
+    tags: Vec<String>,
+    patch_version: u32,
+    patches_path: PathBuf,
+    verify_cache: bool,
+    // loaded by the stages writing packages
+    signing_key: Option<SigningKey>,
+    package_keys: PackageKeys,
+    delta_limit: Arc<DeltaLimit>,
+}
+
+async fn build_platform(
+    unity_path: &Path,
+    project_path: &Path,
+    platform: &str,
+    full: bool,
+) -> Result<()> {
+    // build base app with full res, or incremental res
+    if !run_unity_build(unity_path, project_path, platform, full).await? {
+        return Err(anyhow::anyhow!(format!(
+            "Failed to exec Unity build for {}",
+            platform
+        )));
+    }
+    Ok(())
+}
+
+// the error of the first failed packaging among the finished ones
+fn finished_packaging(packaging: &mut JoinSet<Result<()>>) -> Result<()> {
+    while let Some(joined) = packaging.try_join_next() {
+        joined??;
+    }
+    Ok(())
+}
+
+// write Version.txt, transform and hash the built files of a platform and keep them in the store
+async fn hash_platform(release: &ReleaseContext, platform: &str) -> Result<FileManifest> {
+    let project_path = &release.project_path;
+    let giu_config = &release.giu_config;
+    let patch_version = release.patch_version;
+    let hash_algorithm = giu_config.hash_algorithm;
+    let hash_workers = giu_config.hash_workers.max(1);
 
-    let tags = get_git_tags(&project_path, &loader_version).await?;
+    let platform_folder = project_path.join("ServerData").join(platform);
+
+    // generate Version.txt
+    let version_file = platform_folder.join("Version.txt");
+    fs::write(&version_file, format!("{}", patch_version))?;
+
+    // obfuscate the bundles before they are hashed, the packages carry the transformed bytes
+    let transform = tokio::task::block_in_place(|| {
+        bundle_transform::transform_platform(
+            platform,
+            &platform_folder,
+            giu_config.file_filter(platform),
+            &giu_config.bundle_transform(platform),
+        )
+    })?;
+
+    // generate file hash list
+    let hash_cache_file = HashCache::path(project_path, platform);
+    let mut hash_cache = HashCache::load(&hash_cache_file, hash_algorithm);
+    let manifest = folder_hash_list(
+        &platform_folder,
+        giu_config.file_filter(platform),
+        hash_algorithm,
+        hash_workers,
+        &mut hash_cache,
+        release.verify_cache,
+    )
+    .await?;
+    hash_cache.save(&hash_cache_file)?;
+    let manifest = FileManifest {
+        transform,
+        ..manifest
+    };
+    let hash_file = platform_folder.join("file-hash.csv");
+    manifest.save(&hash_file)?;
+
+    // keep the bundles now, the version is published in the store once it is tagged
+    let store = ArtifactStore::new(project_path, &release.loader_version, platform);
+    let store_cache_file = HashCache::store_path(project_path, platform);
+    let mut store_cache = HashCache::load(&store_cache_file, HashAlgorithm::Sha256);
+    store.stage(
+        &platform_folder,
+        &manifest,
+        patch_version,
+        &mut store_cache,
+        release.verify_cache,
+    )?;
+    store_cache.save(&store_cache_file)?;
+
+    Ok(manifest)
+}
+
+// the manifest written by `hash` for the version being released
+fn load_hashed_manifest(release: &ReleaseContext, platform: &str) -> Result<FileManifest> {
+    let platform_folder = release.project_path.join("ServerData").join(platform);
+    let version_file = platform_folder.join("Version.txt");
+    let hashed_version = fs::read_to_string(&version_file).unwrap_or_default();
+    if hashed_version.trim() != release.patch_version.to_string() {
+        return Err(anyhow::anyhow!(
+            "{} is not hashed for version {}, run hash first",
+            platform,
+            release.patch_version
+        ));
+    }
+    FileManifest::load(&platform_folder.join("file-hash.csv"))
+}
+
+// hash, store and zip the built files of a platform, then write its update_info
+async fn package_platform(
+    release: Arc<ReleaseContext>,
+    platform: String,
+    stamp: ReleaseStamp,
+) -> Result<()> {
+    let manifest = hash_platform(&release, &platform).await?;
+    pack_platform(release, platform, manifest, stamp).await
+}
+
+// zip the hashed files of a platform into the full package and the tag patches, then write its update_info
+async fn pack_platform(
+    release: Arc<ReleaseContext>,
+    platform: String,
+    manifest: FileManifest,
+    stamp: ReleaseStamp,
+) -> Result<()> {
+    let project_path = &release.project_path;
+    let giu_config = &release.giu_config;
+    let compression_policy = &release.compression_policy;
+    let loader_version = &release.loader_version;
+    let tags = &release.tags;
+    let patch_version = release.patch_version;
+    let platform = platform.as_str();
+
+    let delta_patch = giu_config.delta_patch;
+    let patch_workers = giu_config.patch_workers.max(1);
+    let hash_algorithm = manifest.algorithm;
+    let max_patch_ratio = giu_config.max_patch_ratio;
+    let tag_policy = &giu_config.tag_policy;
+
+    let platform_folder = project_path.join("ServerData").join(platform);
+    let hash_file = platform_folder.join("file-hash.csv");
+    let new_file_hash_map = manifest.hash_map();
+    let store = ArtifactStore::new(project_path, loader_version, platform);
+
+    // generate incremental updates
+    let platform_patches_path = release.patches_path.join(platform);
+    fs::create_dir_all(&platform_patches_path)?;
+
+    tracing::info!(
+        "Generating incremental updates for platform: {} full patch",
+        platform
+    );
+
+    let mut diff_files = new_file_hash_map.keys().collect::<Vec<_>>();
+
+    // copy catalog, catalog hash and version file
+    let extra_files = package_extra_files(loader_version);
+    diff_files.extend(extra_files.iter());
+
+    let full_patch_file_name = format!("{}-full.zip", patch_version);
+    let patch_file = platform_patches_path.join(&full_patch_file_name);
+
+    // compress every file once, the patches raw copy the compressed entries
+    let entry_cache_file = project_path
+        .join("Library")
+        .join("GIUEntryCache")
+        .join(format!("{}.zip", platform));
+    fs::create_dir_all(entry_cache_file.parent().unwrap())?;
+    let mut entry_cache = EntryCache::build(
+        &platform_folder,
+        &diff_files,
+        &entry_cache_file,
+        compression_policy,
+    )?;
+
+    tracing::info!("Generated patch full");
+    file_zip::compress_with_cache(
+        &platform_folder,
+        &diff_files,
+        &patch_file,
+        false,
+        compression_policy,
+        &mut entry_cache,
+    )?;
+    drop(entry_cache);
+
+    // the patches are weighed against the plain zip, they are encrypted after
+    let full_file_bytes = fs::metadata(&patch_file)?.len();
+    let package_keys = &release.package_keys;
+    if let Some(key) = package_keys.current.as_ref() {
+        file_zip::encrypt_package(&patch_file, key)?;
+    }
+
+    // older tags chain through a checkpoint when its patch for the tag was kept
+    let is_checkpoint = tag_policy.is_checkpoint(patch_version);
+    let tag_plan = plan_tags(tags, tag_policy, patch_version);
+    let mut direct_tags = tag_plan.direct;
+    let mut chained_tags = Vec::new();
+    for (tag, checkpoint) in tag_plan.chained {
+        let checkpoint_down = format!(
+            "{}/{}.zip",
+            checkpoint_folder_name(tag_patch_version(&checkpoint)),
+            tag
+        );
+        if platform_patches_path.join(&checkpoint_down).is_file() {
+            tracing::info!("Tag: {} chains through: {}", tag, checkpoint);
+            chained_tags.push((tag, checkpoint, checkpoint_down));
+        } else {
+            tracing::warn!(
+                "Tag: {} checkpoint patch {} not found, patch directly",
+                tag,
+                checkpoint_down
+            );
+            direct_tags.push(tag);
+        }
+    }
+    direct_tags.sort_by_key(|tag| std::cmp::Reverse(tag_patch_version(tag)));
+
+    // the tags are independent, patch at most `patch_workers` of them at the same time,
+    // every log line of a task carries its tag
+    let context = Arc::new(TagPatchContext {
+        project_path: project_path.clone(),
+        platform: platform.to_string(),
+        platform_folder: platform_folder.clone(),
+        platform_patches_path: platform_patches_path.clone(),
+        loader_version: loader_version.clone(),
+        patch_version,
+        is_checkpoint,
+        hash_file: hash_file.clone(),
+        hash_algorithm,
+        new_file_hash_map,
+        store,
+        delta_patch,
+        compression_policy: compression_policy.clone(),
+        entry_cache_file: entry_cache_file.clone(),
+        full_patch_file_name: full_patch_file_name.clone(),
+        full_file_bytes,
+        max_patch_ratio,
+        package_keys: package_keys.clone(),
+        delta_limit: release.delta_limit.clone(),
+    });
+    let semaphore = Arc::new(Semaphore::new(patch_workers));
+    let mut tasks = JoinSet::new();
+    for (index, tag) in direct_tags.iter().enumerate() {
+        let context = context.clone();
+        let semaphore = semaphore.clone();
+        let tag = tag.to_string();
+        let span = tracing::info_span!("patch", tag = %tag);
+        tasks.spawn(
+            async move {
+                let _permit = semaphore.acquire_owned().await?;
+                let down = generate_tag_patch(&context, &tag).await?;
+                anyhow::Ok((index, down))
+            }
+            .instrument(span),
+        );
+    }
+
+    // keep the patches in tag order whichever task finishes first,
+    // an error drops the set and aborts the other tasks
+    let mut downs = direct_tags.iter().map(|_| None).collect::<Vec<_>>();
+    while let Some(result) = tasks.join_next().await {
+        let (index, down) = result??;
+        downs[index] = Some(down);
+    }
+
+    // every package is read back from disk, so the sizes and hashes match what the clients download
+    let version = patch_version.to_string();
+    let mut update_info = UpdateInfo::new(
+        stamp,
+        PackageInfo::read(
+            &platform_patches_path,
+            &full_patch_file_name,
+            None,
+            &version,
+            package_keys,
+        )?,
+    );
+    for (tag, down) in direct_tags.iter().zip(downs.into_iter().flatten()) {
+        let from = tag_patch_version(tag).to_string();
+        let package = PackageInfo::read(
+            &platform_patches_path,
+            &down,
+            Some(&from),
+            &version,
+            package_keys,
+        )?;
+        tracing::info!(
+            "Tag: {} patch: {}, {}",
+            tag,
+            package.file,
+            format_size(package.size)
+        );
+        update_info.patches.push(package);
+    }
+    for (tag, checkpoint, checkpoint_down) in chained_tags.iter() {
+        // a checkpoint patch kept before the encryption was enabled
+        let checkpoint_file = platform_patches_path.join(checkpoint_down);
+        if let Some(key) = package_keys.current.as_ref() {
+            if file_zip::package_key_id(&checkpoint_file)?.is_none() {
+                file_zip::encrypt_package(&checkpoint_file, key)?;
+            }
+        }
+        update_info.chains.push(PackageInfo::read(
+            &platform_patches_path,
+            checkpoint_down,
+            Some(&tag_patch_version(tag).to_string()),
+            &tag_patch_version(checkpoint).to_string(),
+            package_keys,
+        )?);
+    }
+    update_info.transform = manifest.transform.clone();
+    update_info.set_created()?;
+    update_info.sort();
+    if giu_config.legacy_update_info {
+        update_info.legacy = Some(update_info.legacy_fields());
+    }
+
+    fs::remove_file(&entry_cache_file)?;
+
+    update_info.save(&platform_patches_path.join("update_info"))?;
+
+    // reopen every package the clients download before the release is tagged
+    tokio::task::block_in_place(|| {
+        patch_verify::verify_platform_patches(
+            &platform_folder,
+            &platform_patches_path,
+            loader_version,
+            package_keys,
+            &context.store,
+        )
+    })?;
+
+    // sign what was verified, the signatures are deterministic so unchanged packages keep theirs
+    if let Some(key) = release.signing_key.as_ref() {
+        let update_info_file = platform_patches_path.join("update_info");
+        let mut signed = BTreeSet::new();
+        for package in update_info.packages() {
+            if signed.insert(&package.file) {
+                signing::sign_file(key, &platform_patches_path.join(&package.file))?;
+            }
+        }
+        signing::sign_file(key, &update_info_file)?;
+        tracing::info!("Signed update_info and {} packages", signed.len());
+    }
+
+    Ok(())
+}
+
+// the config, the tags of the loader version and the Game Patch Version the stages work on
+async fn prepare_release(project_path: &Path, verify_cache: bool) -> Result<ReleaseContext> {
+    let giu_config = load_giu_config(project_path)?;
+    let compression_policy = CompressionPolicy::new(&giu_config.compression)?;
+
+    let loader_version = load_loader_version(project_path)?;
+
+    let tags = get_git_tags(project_path, &loader_version).await?;
 
     tracing::info!(
         "Loader version: {}, Will generate incremental updates for tags:",
         loader_version,
     );
     for tag in tags.iter() {
-        let tag_info = git_cmd::get_git_tag_info(&project_path, tag).await?;
+        let tag_info = git_cmd::get_git_tag_info(project_path, tag).await?;
         tracing::info!("    {}", tag_info);
     }
 
@@ -143,190 +1088,340 @@ async fn generate_incremental_updates() -> Result<()> {
         0
     } else {
         let last_tag = tags.first().unwrap();
-        let patch_version = last_tag.split('-').last().unwrap().parse::<u32>().unwrap();
+        let patch_version = tag_patch_version(last_tag);
         tracing::info!("Last tag: {}, patch version: {}", last_tag, patch_version);
         patch_version + 1
     };
 
-    // unity-project-folder/../host/serve/loader-version
-    let patches_path = project_path
-        .parent()
-        .unwrap()
-        .join("host")
-        .join("serve")
-        .join(&loader_version);
+    let patches_path = serve_path(project_path, &loader_version);
     fs::create_dir_all(&patches_path)?;
 
+    let delta_limit = Arc::new(DeltaLimit {
+        max_size: giu_config.delta_max_size_mb * 1024 * 1024,
+        workers: Semaphore::new(giu_config.delta_workers.max(1)),
+    });
+
+    Ok(ReleaseContext {
+        project_path: project_path.to_path_buf(),
+        giu_config,
+        compression_policy,
+        loader_version,
+        tags,
+        patch_version,
+        patches_path,
+        verify_cache,
+        signing_key: None,
+        package_keys: PackageKeys::default(),
+        delta_limit,
+    })
+}
+
+// the signing key and the encryption keys of the packages
+fn load_release_keys(release: &mut ReleaseContext) -> Result<()> {
+    let giu_config = &release.giu_config;
+    release.signing_key = match giu_config.signing.as_ref() {
+        Some(signing) => signing::load_signing_key(signing, &release.project_path)?,
+        None => None,
+    };
+    match release.signing_key.as_ref() {
+        Some(key) => tracing::info!("Signing with public key: {}", signing::public_key_hex(key)),
+        None => tracing::warn!("No signing key configured, update_info and packages are unsigned"),
+    }
+    release.package_keys =
+        PackageKeys::load(giu_config.encryption.as_ref(), &release.project_path)?;
+    if let Some(key) = release.package_keys.current.as_ref() {
+        tracing::info!("Encrypting packages with key: {}", key.id);
+    }
+    Ok(())
+}
+
+fn load_unity_path(giu_config: &GIUConfig) -> Result<PathBuf> {
+    let unity_path = PathBuf::from(cyg_to_win(&giu_config.unity_path));
+    if !unity_path.is_file() {
+        return Err(anyhow::anyhow!(
+            "Unity path not configured in .giu_config.toml"
+        ));
+    }
+    Ok(unity_path)
+}
+
+// every build is committed with the release, start from a clean repository
+async fn check_git_clean(project_path: &Path) -> Result<()> {
+    if !is_git_repo(project_path).await? {
+        return Err(anyhow::anyhow!("project folder is not in Git repository"));
+    }
+
+    if !is_git_repo_clean(project_path).await? {
+        return Err(anyhow::anyhow!("project folder has uncommitted changes"));
+    }
+    Ok(())
+}
+
+fn next_stamp(release: &ReleaseContext) -> Result<ReleaseStamp> {
+    let stamp = next_release_stamp(
+        &release.patches_path,
+        &release.loader_version,
+        &release.giu_config.platforms,
+        release.giu_config.update_info_expiry_days,
+    )?;
+    tracing::info!("Release counter: {}", stamp.release);
+    Ok(stamp)
+}
+
+async fn commit_release(release: &ReleaseContext) -> Result<()> {
+    let tag = format!("{}-{}", release.loader_version, release.patch_version);
+    git_commit_with_tag(&release.project_path, &tag, &format!("build: {}", tag)).await?;
+
+    // only a tagged version is restored or used as the base of the next patches
+    for platform in release.giu_config.platforms.iter() {
+        ArtifactStore::new(&release.project_path, &release.loader_version, platform)
+            .promote(release.patch_version)?;
+    }
+    Ok(())
+}
+
+// build, hash and pack every platform, then commit and tag the release
+async fn run_release(project_path: &Path, verify_cache: bool) -> Result<()> {
+    check_git_clean(project_path).await?;
+
+    let mut release = prepare_release(project_path, verify_cache).await?;
+    let unity_path = load_unity_path(&release.giu_config)?;
+    load_release_keys(&mut release)?;
+    let platforms = select_platforms(&release.giu_config, &PlatformArgs { platforms: vec![] })?;
+    let stamp = next_stamp(&release)?;
+    let release = Arc::new(release);
+
+    // Unity builds one platform at a time, a built platform is packaged while Unity builds the next one
+    let mut packaging = JoinSet::new();
+    let mut result = Ok(());
     for platform in platforms.iter() {
-        if tags.is_empty() {
-            // build base app with full res
-            if !run_unity_build(unity_path, &project_path, platform, true).await? {
-                return Err(anyhow::anyhow!(format!(
-                    "Failed to exec Unity build for {}",
-                    platform
-                )));
-            }
-        } else {
-            // build incremental res
-            if !run_unity_build(unity_path, &project_path, platform, false).await? {
-                return Err(anyhow::anyhow!(format!(
-                    "Failed to exec Unity build for {}",
-                    platform
-                )));
-            }
+        // no more builds once a packaging failed
+        result = finished_packaging(&mut packaging);
+        if result.is_err() {
+            break;
+        }
+        result = build_platform(&unity_path, project_path, platform, release.tags.is_empty()).await;
+        if result.is_err() {
+            break;
         }
+        let span = tracing::info_span!("platform", platform = %platform);
+        packaging.spawn(
+            package_platform(release.clone(), platform.to_string(), stamp.clone()).instrument(span),
+        );
+    }
 
-        let platform_folder = project_path.join("ServerData").join(platform);
+    // wait for the running packaging even after a failure, so no patch is left half written
+    while let Some(joined) = packaging.join_next().await {
+        let joined = joined.map_err(anyhow::Error::from).and_then(|r| r);
+        match joined {
+            Err(e) if result.is_err() => tracing::error!("{}", e),
+            Err(e) => result = Err(e),
+            Ok(()) => {}
+        }
+    }
+    result?;
 
-        // generate Version.txt
-        let version_file = platform_folder.join("Version.txt");
-        fs::write(&version_file, format!("{}", patch_version))?;
+    tracing::info!("Incremental updates generated successfully");
 
-        // generate file hash list
-        let hashes = folder_hash_list(&platform_folder).await?;
-        let hash_file = platform_folder.join("file-hash.csv");
-        fs::write(&hash_file, hashes)?;
+    commit_release(&release).await
+}
 
-        let new_file_hash_map = load_file_hash_map(&hash_file)?;
+async fn build_platforms(project_path: &Path, platforms: &PlatformArgs) -> Result<()> {
+    check_git_clean(project_path).await?;
 
-        // generate incremental updates
-        let platform_patches_path = patches_path.join(platform);
-        fs::create_dir_all(&platform_patches_path)?;
-
-        let mut platform_patch_info_map = HashMap::new();
-
-        if !tags.is_empty() {
-            for tag in tags.iter() {
-                tracing::info!(
-                    "Generating incremental updates for platform: {} patch for: {}",
-                    platform,
-                    tag
-                );
-                let tag_folder = platform_patches_path.join(tag);
-                fs::create_dir_all(&tag_folder)?;
-
-                export_file_in_git_by_tag(
-                    &project_path,
-                    tag,
-                    hash_file.to_str().unwrap(),
-                    &tag_folder,
-                )
-                .await?;
-
-                // the file in archive.zip is relative to the project folder
-                let tag_file_hash_map = load_file_hash_map(
-                    &tag_folder
-                        .join("ServerData")
-                        .join(platform)
-                        .join("file-hash.csv"),
-                )?;
-
-                let mut diff_files = Vec::new();
-                let mut diff_file_list = String::new();
-                for (file_name, new_hash) in new_file_hash_map.iter() {
-                    let old_hash = tag_file_hash_map.get(file_name);
-                    if old_hash.is_none() || old_hash.unwrap() != new_hash {
-                        diff_files.push(file_name);
-                        diff_file_list.push_str(&format!("{},{}\n", file_name, new_hash));
-                    }
-                }
-
-                let diff_file_list_name = format!("diff-{}.csv", tag);
-                let diff_file = platform_folder.join(&diff_file_list_name);
-                fs::write(&diff_file, diff_file_list)?;
-                diff_files.push(&diff_file_list_name);
-
-                // copy catalog
-                let catalog_file = format!("catalog_{}.json", loader_version);
-                diff_files.push(&catalog_file);
-                // copy catalog hash file
-                let catalog_hash_file = format!("catalog_{}.hash", loader_version);
-                diff_files.push(&catalog_hash_file);
-                // copy version file
-                let version_file = "Version.txt".to_string();
-                diff_files.push(&version_file);
-
-                let patch_file = platform_patches_path.join(format!("{}.zip", tag));
-                tracing::info!("Generated patch for tag: {}", tag);
-                file_zip::compress(&platform_folder, &diff_files, &patch_file, false)?;
-
-                let file_size = fs::metadata(patch_file)?.len();
-                let file_size = file_size as f64 / 1048576_f64; // 1024 x 1024
-                let file_size = if file_size < 0.01 {
-                    format!("{:.2} K", file_size * 1024_f64)
-                } else {
-                    format!("{:.2} M", file_size)
-                };
-                let tag_version = tag.split('-').last().unwrap();
-                let patch_info = PatchInfo {
-                    ver: tag_version.to_string(),
-                    down: format!("{}.zip", tag),
-                    size: file_size,
-                };
-                platform_patch_info_map.insert(tag.to_string(), patch_info);
-            }
-        }
+    let release = prepare_release(project_path, false).await?;
+    let unity_path = load_unity_path(&release.giu_config)?;
+    for platform in select_platforms(&release.giu_config, platforms)?.iter() {
+        build_platform(&unity_path, project_path, platform, release.tags.is_empty()).await?;
+        tracing::info!("Built {}", platform);
+    }
+    Ok(())
+}
 
+async fn hash_platforms(
+    project_path: &Path,
+    platforms: &PlatformArgs,
+    verify_cache: bool,
+) -> Result<()> {
+    let release = prepare_release(project_path, verify_cache).await?;
+    for platform in select_platforms(&release.giu_config, platforms)?.iter() {
+        let span = tracing::info_span!("platform", platform = %platform);
+        let manifest = hash_platform(&release, platform).instrument(span).await?;
         tracing::info!(
-            "Generating incremental updates for platform: {} full patch",
-            platform
+            "Hashed {} files of {} for version {}",
+            manifest.entries.len(),
+            platform,
+            release.patch_version
         );
+    }
+    Ok(())
+}
 
-        let mut diff_files = new_file_hash_map.keys().collect::<Vec<_>>();
-
-        // copy catalog
-        let catalog_file = format!("catalog_{}.json", loader_version);
-        diff_files.push(&catalog_file);
-        // copy catalog hash file
-        let catalog_hash_file = format!("catalog_{}.hash", loader_version);
-        diff_files.push(&catalog_hash_file);
-        // copy version file
-        let version_file = "Version.txt".to_string();
-        diff_files.push(&version_file);
-
-        let full_patch_file_name = format!("{}-full.zip", patch_version);
-        let patch_file = platform_patches_path.join(&full_patch_file_name);
-        tracing::info!("Generated patch full");
-        file_zip::compress(&platform_folder, &diff_files, &patch_file, false)?;
-
-        let full_file_size = fs::metadata(patch_file)?.len();
-        let full_file_size = full_file_size as f64 / 1048576_f64; // 1024 x 1024
-        let full_file_size = if full_file_size < 0.01 {
-            format!("{:.2} K", full_file_size * 1024_f64)
-        } else {
-            format!("{:.2} M", full_file_size)
-        };
+// what each tag patch would carry, without zipping
+async fn diff_platforms(project_path: &Path, platforms: &PlatformArgs) -> Result<()> {
+    let release = prepare_release(project_path, false).await?;
+    for platform in select_platforms(&release.giu_config, platforms)?.iter() {
+        let manifest = load_hashed_manifest(&release, platform)?;
+        let new_file_hash_map = manifest.hash_map();
+        let store = ArtifactStore::new(project_path, &release.loader_version, platform);
+        let hash_file = project_path
+            .join("ServerData")
+            .join(platform)
+            .join("file-hash.csv");
+        // unity-project-folder/Library/GIUDiff/platform
+        let diff_folder = project_path.join("Library").join("GIUDiff").join(platform);
+        for tag in release.tags.iter() {
+            let (_, file_diff) = diff_tag(
+                project_path,
+                platform,
+                &hash_file,
+                manifest.algorithm,
+                &new_file_hash_map,
+                &store,
+                tag,
+                &diff_folder.join(tag),
+            )
+            .await?;
+            let changed_size = file_diff
+                .changed
+                .iter()
+                .map(|name| manifest.entries[name].size)
+                .sum::<u64>();
+            tracing::info!(
+                "{} tag: {} changed files: {}, {}, removed files: {}",
+                platform,
+                tag,
+                file_diff.changed.len(),
+                format_size(changed_size),
+                file_diff.removed.len()
+            );
+        }
+        if diff_folder.is_dir() {
+            fs::remove_dir_all(&diff_folder)?;
+        }
+    }
+    Ok(())
+}
 
-        let mut platform_patch_info = PlatformPatchInfo {
-            ver: patch_version.to_string(),
-            down: full_patch_file_name,
-            size: full_file_size,
-            vers: Vec::new(),
-            downs: Vec::new(),
-            sizes: Vec::new(),
-        };
+async fn pack_platforms(project_path: &Path, platforms: &PlatformArgs) -> Result<()> {
+    let mut release = prepare_release(project_path, false).await?;
+    load_release_keys(&mut release)?;
+    let platforms = select_platforms(&release.giu_config, platforms)?;
+    let manifests = platforms
+        .iter()
+        .map(|platform| load_hashed_manifest(&release, platform))
+        .collect::<Result<Vec<_>>>()?;
+    let stamp = next_stamp(&release)?;
+    let release = Arc::new(release);
+
+    for (platform, manifest) in platforms.into_iter().zip(manifests) {
+        let span = tracing::info_span!("platform", platform = %platform);
+        pack_platform(release.clone(), platform, manifest, stamp.clone())
+            .instrument(span)
+            .await?;
+    }
+    tracing::info!("Incremental updates generated successfully");
+    Ok(())
+}
+
+// keep the served update_info accepted by the clients when no new version is released before it expires,
+// only the release counter, issued and expires change, and the signature of update_info
+fn restamp_platforms(project_path: &Path, platforms: &PlatformArgs) -> Result<()> {
+    let giu_config = load_giu_config(project_path)?;
+    let loader_version = load_loader_version(project_path)?;
+    let patches_path = serve_path(project_path, &loader_version);
+    let signing_key = match giu_config.signing.as_ref() {
+        Some(signing) => signing::load_signing_key(signing, project_path)?,
+        None => None,
+    };
+    if signing_key.is_none() {
+        tracing::warn!("No signing key configured, update_info is unsigned");
+    }
+
+    let platforms = select_platforms(&giu_config, platforms)?;
+    // load every update_info first, so that a missing one changes nothing
+    let update_infos = platforms
+        .iter()
+        .map(|platform| {
+            let update_info_file = patches_path.join(platform).join("update_info");
+            let update_info = UpdateInfo::load(&update_info_file)?;
+            Ok((update_info_file, update_info))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let stamp = next_release_stamp(
+        &patches_path,
+        &loader_version,
+        &giu_config.platforms,
+        giu_config.update_info_expiry_days,
+    )?;
 
-        for (_tag, patch_info) in platform_patch_info_map.iter() {
-            platform_patch_info.vers.push(patch_info.ver.to_string());
-            platform_patch_info.downs.push(patch_info.down.to_string());
-            platform_patch_info.sizes.push(patch_info.size.to_string());
+    for (update_info_file, mut update_info) in update_infos {
+        update_info.stamp = stamp.clone();
+        update_info.save(&update_info_file)?;
+        if let Some(key) = signing_key.as_ref() {
+            signing::sign_file(key, &update_info_file)?;
         }
+        tracing::info!(
+            "Restamped {}: version {}, release {}",
+            update_info_file.display(),
+            update_info.version,
+            stamp.release
+        );
+    }
+    Ok(())
+}
 
-        let platform_patch_info_file = platform_patches_path.join("update_info");
-        let platform_patch_info_str = serde_json::to_string(&platform_patch_info)?;
-        fs::write(&platform_patch_info_file, platform_patch_info_str)?;
+// tag the version once every platform is packed for it
+async fn release_platforms(project_path: &Path) -> Result<()> {
+    let release = prepare_release(project_path, false).await?;
+    for platform in release.giu_config.platforms.iter() {
+        let update_info_file = release.patches_path.join(platform).join("update_info");
+        let packed = UpdateInfo::load(&update_info_file)
+            .map(|update_info| update_info.version == release.patch_version.to_string())
+            .unwrap_or(false);
+        if !packed {
+            return Err(anyhow::anyhow!(
+                "{} is not packed for version {}, run pack first",
+                platform,
+                release.patch_version
+            ));
+        }
     }
+    commit_release(&release).await
+}
 
-    tracing::info!("Incremental updates generated successfully");
+async fn generate_incremental_updates() -> Result<()> {
+    let args: Args = Args::parse();
+    let project_path = cyg_to_win(&args.project_path);
+    let project_path = std::path::absolute(Path::new(&project_path))?;
+    println!("project_path: {:?}", project_path);
 
-    git_commit_with_tag(
-        &project_path,
-        &format!("{}-{}", loader_version, patch_version),
-        &format!("build: {}-{}", loader_version, patch_version),
-    )
-    .await?;
+    if !project_path.is_dir() {
+        return Err(anyhow::anyhow!("Invalid project path"));
+    }
 
-    Ok(())
+    let _guards = log_util::init(&project_path);
+
+    match args.command {
+        Command::Init => init_giu_config(&project_path),
+        Command::Run { verify_cache } => run_release(&project_path, verify_cache).await,
+        Command::Build { platforms } => build_platforms(&project_path, &platforms).await,
+        Command::Hash {
+            platforms,
+            verify_cache,
+        } => hash_platforms(&project_path, &platforms, verify_cache).await,
+        Command::Diff { platforms } => diff_platforms(&project_path, &platforms).await,
+        Command::Pack { platforms } => pack_platforms(&project_path, &platforms).await,
+        Command::Release => release_platforms(&project_path).await,
+        Command::Restamp { platforms } => restamp_platforms(&project_path, &platforms),
+        Command::Verify {
+            platforms,
+            public_key,
+        } => verify_packages(&project_path, &platforms, public_key.as_deref()),
+        Command::Simulate { platforms } => simulate_patches(&project_path, &platforms).await,
+        Command::List => list_release(&project_path).await,
+        Command::Restore { patch_version } => restore_from_store(&project_path, patch_version),
+    }
 }
 
 #[tokio::main]
@@ -336,6 +1431,8 @@ async fn main() {
         Err(e) => {
             tracing::error!("{}", e);
             println!("Error: {}", e);
+            // scripts and CI see the failure in the exit code
+            std::process::exit(1);
         }
     }
 }
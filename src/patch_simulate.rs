@@ -0,0 +1,314 @@
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::artifact_store::ArtifactStore;
+use crate::file_check::same_content;
+use crate::file_delta;
+use crate::file_manifest::{package_extra_files, FileManifest};
+use crate::file_zip::{self, PackageKeys};
+use crate::folder_hash_list::list_files;
+use crate::git_cmd::export_files_in_git_by_tag;
+use crate::giu_config::FileFilter;
+use crate::tag_policy::tag_patch_version;
+use crate::update_info::UpdateInfo;
+
+/// Apply a package to the files of a version like a client does,
+/// the full package replaces all the files, a patch rebuilds its deltas and deletes the removed files
+pub(crate) fn apply_package(
+    folder: &Path,
+    package: &Path,
+    full: bool,
+    keys: &PackageKeys,
+) -> Result<()> {
+    if full && folder.is_dir() {
+        fs::remove_dir_all(folder)?;
+    }
+    fs::create_dir_all(folder)?;
+
+    let archive = file_zip::open_package(package, keys)?;
+    let root_entry = |prefix: &str| {
+        archive
+            .file_names()
+            .find(|name| name.starts_with(prefix) && name.ends_with(".csv") && !name.contains('/'))
+            .map(|name| name.to_string())
+    };
+    let diff_list_name = root_entry("diff-");
+    let remove_list_name = root_entry("remove-");
+    drop(archive);
+
+    file_zip::extract_package(package, folder, keys)?;
+
+    if let Some(diff_list_name) = diff_list_name {
+        let diff_list = folder.join(&diff_list_name);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&diff_list)?;
+        for record in reader.records() {
+            let record = record?;
+            if record.get(2) != Some("delta") {
+                continue;
+            }
+            let file = folder.join(&record[0]);
+            let delta_file = folder.join(format!("{}.delta", &record[0]));
+            let old = if file.is_file() {
+                fs::read(&file)?
+            } else {
+                Vec::new()
+            };
+            let new = file_delta::apply(&old, &fs::read(&delta_file)?)
+                .map_err(|e| anyhow::anyhow!("delta of {}: {}", &record[0], e))?;
+            fs::write(&file, new)?;
+            fs::remove_file(&delta_file)?;
+        }
+        fs::remove_file(diff_list)?;
+    }
+
+    if let Some(remove_list_name) = remove_list_name {
+        let remove_list = folder.join(&remove_list_name);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&remove_list)?;
+        for record in reader.records() {
+            let file = folder.join(&record?[0]);
+            if file.is_file() {
+                fs::remove_file(file)?;
+            }
+        }
+        fs::remove_file(remove_list)?;
+    }
+
+    Ok(())
+}
+
+/// Compare the patched files with the build, returns the differences
+pub(crate) fn compare_folders(
+    folder: &Path,
+    platform_folder: &Path,
+    manifest: &FileManifest,
+    filter: &FileFilter,
+    extra_files: &[String],
+) -> Result<Vec<String>> {
+    let mut errors = Vec::new();
+    for name in manifest.entries.keys().chain(extra_files.iter()) {
+        let file = folder.join(name);
+        if !file.is_file() {
+            errors.push(format!("{} missing", name));
+        } else if !same_content(&file, &platform_folder.join(name))? {
+            errors.push(format!("{} differs", name));
+        }
+    }
+    for (name, _) in list_files(folder, filter)? {
+        if !manifest.entries.contains_key(&name) {
+            errors.push(format!("{} left behind", name));
+        }
+    }
+    errors.sort();
+    Ok(errors)
+}
+
+// the files of a tag, from the store, or from git when the tag was published before the store
+async fn restore_tag(
+    project_path: &Path,
+    platform_folder: &Path,
+    store: &ArtifactStore,
+    tag: &str,
+    des_folder: &Path,
+) -> Result<()> {
+    if let Some(objects) = store.release_objects(tag_patch_version(tag))? {
+        return store.restore(&objects, des_folder);
+    }
+
+    let export_folder = des_folder.with_extension("git");
+    export_files_in_git_by_tag(
+        project_path,
+        tag,
+        &[platform_folder.to_str().unwrap()],
+        &export_folder,
+    )
+    .await?;
+    // the file in archive.zip is relative to the project folder
+    fs::rename(
+        export_folder.join(platform_folder.strip_prefix(project_path)?),
+        des_folder,
+    )?;
+    fs::remove_dir_all(&export_folder)?;
+    Ok(())
+}
+
+/// Apply every package of the update_info of a platform to the files of its base version,
+/// and check the result is identical to the build
+pub(crate) async fn simulate_platform(
+    project_path: &Path,
+    loader_version: &str,
+    platform: &str,
+    filter: &FileFilter,
+    patches_path: &Path,
+    keys: &PackageKeys,
+) -> Result<()> {
+    let platform_folder = project_path.join("ServerData").join(platform);
+    let platform_patches_path = patches_path.join(platform);
+    let manifest = FileManifest::load(&platform_folder.join("file-hash.csv"))?;
+    let update_info = UpdateInfo::load(&platform_patches_path.join("update_info"))?;
+    let store = ArtifactStore::new(project_path, loader_version, platform);
+    let extra_files = package_extra_files(loader_version);
+
+    // unity-project-folder/Library/GIUSimulate/platform
+    let work_folder = project_path
+        .join("Library")
+        .join("GIUSimulate")
+        .join(platform);
+    if work_folder.is_dir() {
+        fs::remove_dir_all(&work_folder)?;
+    }
+
+    // base version of each simulation, none for a fresh install, and the packages applied in order
+    let downs = update_info
+        .patches
+        .iter()
+        .filter_map(|package| Some((package.from.as_ref()?, &package.file)))
+        .collect::<HashMap<_, _>>();
+    let mut simulations: Vec<(Option<String>, Vec<&String>)> =
+        vec![(None, vec![&update_info.full.file])];
+    for package in update_info.patches.iter() {
+        let from = package.from.as_deref().unwrap_or_default();
+        simulations.push((
+            Some(format!("{}-{}", loader_version, from)),
+            vec![&package.file],
+        ));
+    }
+    for package in update_info.chains.iter() {
+        let from = package.from.as_deref().unwrap_or_default();
+        let to_down = downs
+            .get(&package.to)
+            .ok_or_else(|| anyhow::anyhow!("no package from checkpoint version {}", package.to))?;
+        simulations.push((
+            Some(format!("{}-{}", loader_version, from)),
+            vec![&package.file, to_down],
+        ));
+    }
+
+    let mut failed = 0;
+    for (index, (tag, packages)) in simulations.iter().enumerate() {
+        let folder: PathBuf = work_folder.join(index.to_string());
+        if let Some(tag) = tag {
+            restore_tag(project_path, &platform_folder, &store, tag, &folder).await?;
+        }
+        for package in packages.iter() {
+            apply_package(
+                &folder,
+                &platform_patches_path.join(package),
+                **package == update_info.full.file,
+                keys,
+            )?;
+        }
+
+        let from = tag.as_deref().unwrap_or("fresh install");
+        let errors = compare_folders(&folder, &platform_folder, &manifest, filter, &extra_files)?;
+        if errors.is_empty() {
+            tracing::info!("simulate {} {}: {:?} ok", platform, from, packages);
+        } else {
+            failed += 1;
+            for error in errors.iter() {
+                tracing::error!("simulate {} {}: {:?} {}", platform, from, packages, error);
+            }
+        }
+        fs::remove_dir_all(&folder)?;
+    }
+    fs::remove_dir_all(&work_folder)?;
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} simulated updates of {} differ from the build",
+            failed,
+            simulations.len(),
+            platform
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_manifest::FileEntry;
+    use crate::file_zip::CompressionPolicy;
+
+    #[test]
+    fn test_apply() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let old = root.join("old");
+        let new = root.join("new");
+        let patch = root.join("patch");
+        for folder in [&old, &new, &patch] {
+            fs::create_dir_all(folder.join("sub")).unwrap();
+        }
+        let b_old = vec![7u8; 4096];
+        let mut b_new = b_old.clone();
+        b_new[100] = 8;
+        fs::write(old.join("a.bundle"), "aaa").unwrap();
+        fs::write(old.join("sub/b.bundle"), &b_old).unwrap();
+        fs::write(old.join("c.bundle"), "ccc").unwrap();
+        fs::write(new.join("a.bundle"), "aaa").unwrap();
+        fs::write(new.join("sub/b.bundle"), &b_new).unwrap();
+        fs::write(new.join("d.bundle"), "ddd").unwrap();
+        fs::write(new.join("Version.txt"), "2").unwrap();
+
+        fs::copy(new.join("d.bundle"), patch.join("d.bundle")).unwrap();
+        fs::copy(new.join("Version.txt"), patch.join("Version.txt")).unwrap();
+        fs::write(
+            patch.join("sub/b.bundle.delta"),
+            file_delta::encode(&b_old, &b_new),
+        )
+        .unwrap();
+        fs::write(
+            patch.join("diff-1-1.csv"),
+            "d.bundle,D,full\nsub/b.bundle,B,delta\n",
+        )
+        .unwrap();
+        fs::write(patch.join("remove-1-1.csv"), "c.bundle,C\n").unwrap();
+        let names = [
+            "Version.txt",
+            "d.bundle",
+            "diff-1-1.csv",
+            "remove-1-1.csv",
+            "sub/b.bundle.delta",
+        ]
+        .map(String::from);
+        let package = root.join("1-1.zip");
+        file_zip::compress(
+            &patch,
+            &names.iter().collect::<Vec<_>>(),
+            &package,
+            false,
+            &CompressionPolicy::default(),
+        )
+        .unwrap();
+
+        let mut manifest = FileManifest::new(Default::default());
+        for name in ["a.bundle", "sub/b.bundle", "d.bundle"] {
+            manifest.insert(FileEntry {
+                path: name.to_string(),
+                size: 0,
+                hash: String::new(),
+            });
+        }
+        let extra_files = ["Version.txt".to_string()];
+        let filter = FileFilter::default();
+
+        let errors = compare_folders(&old, &new, &manifest, &filter, &extra_files).unwrap();
+        assert_eq!(errors.len(), 4);
+
+        apply_package(&old, &package, false, &PackageKeys::default()).unwrap();
+        let errors = compare_folders(&old, &new, &manifest, &filter, &extra_files).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(!old.join("diff-1-1.csv").exists());
+        assert!(!old.join("sub/b.bundle.delta").exists());
+    }
+}
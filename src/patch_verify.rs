@@ -0,0 +1,453 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use crate::artifact_store::ArtifactStore;
+use crate::file_check::{reader_hash, HashAlgorithm};
+use crate::file_delta;
+use crate::file_manifest::{package_extra_files, FileManifest};
+use crate::file_zip::{open_package, PackageKeys};
+use crate::update_info::{unix_now, PackageInfo, UpdateInfo};
+
+/// Reopen every package listed in the update_info of a platform and check it against the build,
+/// the deltas are applied to the bundles of their base version in the store,
+/// returns an error listing the mismatches
+pub(crate) fn verify_platform_patches(
+    platform_folder: &Path,
+    platform_patches_path: &Path,
+    loader_version: &str,
+    keys: &PackageKeys,
+    store: &ArtifactStore,
+) -> Result<()> {
+    let manifest = FileManifest::load(&platform_folder.join("file-hash.csv"))?;
+    let file_hash_map = manifest.hash_map();
+    let update_info = UpdateInfo::load(&platform_patches_path.join("update_info"))?;
+
+    // every package carries the catalog and the version of the build
+    let mut extra_files = HashMap::new();
+    for name in package_extra_files(loader_version) {
+        let hash = reader_hash(
+            &mut File::open(platform_folder.join(&name))?,
+            manifest.algorithm,
+        )?;
+        extra_files.insert(name, hash);
+    }
+
+    let mut errors = Vec::new();
+    if let Err(e) = update_info.check_freshness(loader_version, unix_now()?, None) {
+        errors.push(e.to_string());
+    }
+    if update_info.transform != manifest.transform {
+        errors.push(format!(
+            "update_info: transform {:?}, manifest {:?}",
+            update_info.transform, manifest.transform
+        ));
+    }
+    if !update_info.is_sorted() {
+        errors.push("update_info: packages not sorted latest first".to_string());
+    }
+    if let Some(legacy) = update_info.legacy.as_ref() {
+        if *legacy != update_info.legacy_fields() {
+            errors.push("update_info: legacy fields differ from the packages".to_string());
+        }
+    }
+
+    // the packages of this release, a skipped patch points at the full package
+    let mut verified = BTreeSet::new();
+    for package in std::iter::once(&update_info.full).chain(update_info.patches.iter()) {
+        if package.to != update_info.version {
+            errors.push(format!(
+                "{}: updates to {}, latest is {}",
+                package.file, package.to, update_info.version
+            ));
+        }
+        if !check_package(platform_patches_path, package, keys, &mut errors)? {
+            continue;
+        }
+        if verified.insert(&package.file) {
+            let base_objects = match package.from.as_deref() {
+                Some(from) => store.release_objects(from.parse()?)?,
+                None => None,
+            };
+            verify_package(
+                &platform_patches_path.join(&package.file),
+                &package.file,
+                keys,
+                &file_hash_map,
+                &extra_files,
+                manifest.algorithm,
+                store,
+                base_objects.as_ref(),
+                &mut errors,
+            )?;
+        }
+    }
+
+    // the checkpoint packages were verified by their own release
+    for package in update_info.chains.iter() {
+        check_package(platform_patches_path, package, keys, &mut errors)?;
+    }
+
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            tracing::error!("verify {}", error);
+        }
+        return Err(anyhow::anyhow!(
+            "{} verification errors in {}",
+            errors.len(),
+            platform_patches_path.display()
+        ));
+    }
+    tracing::info!(
+        "verified {} packages in {}",
+        verified.len(),
+        platform_patches_path.display()
+    );
+    Ok(())
+}
+
+// compare the package recorded in update_info with the file on disk, returns false if it is missing
+fn check_package(
+    platform_patches_path: &Path,
+    package: &PackageInfo,
+    keys: &PackageKeys,
+    errors: &mut Vec<String>,
+) -> Result<bool> {
+    if !platform_patches_path.join(&package.file).is_file() {
+        errors.push(format!("{}: not found", package.file));
+        return Ok(false);
+    }
+    let on_disk = PackageInfo::read(
+        platform_patches_path,
+        &package.file,
+        package.from.as_deref(),
+        &package.to,
+        keys,
+    )?;
+    let fields = [
+        ("size", package.size.to_string(), on_disk.size.to_string()),
+        (
+            "uncompressed_size",
+            package.uncompressed_size.to_string(),
+            on_disk.uncompressed_size.to_string(),
+        ),
+        (
+            "file_count",
+            package.file_count.to_string(),
+            on_disk.file_count.to_string(),
+        ),
+        (
+            "remove",
+            package.remove.to_string(),
+            on_disk.remove.to_string(),
+        ),
+        ("sha256", package.sha256.clone(), on_disk.sha256),
+        (
+            "key_id",
+            format!("{:?}", package.key_id),
+            format!("{:?}", on_disk.key_id),
+        ),
+    ];
+    for (field, recorded, actual) in fields {
+        if recorded != actual {
+            errors.push(format!(
+                "{}: update_info {} {}, file {}",
+                package.file, field, recorded, actual
+            ));
+        }
+    }
+    Ok(true)
+}
+
+// apply a delta entry to its base bundle like a client, returns the hash of the rebuilt bundle
+fn rebuilt_hash(
+    delta_entry: &mut impl Read,
+    base: &Path,
+    algorithm: HashAlgorithm,
+) -> Result<String> {
+    let mut delta = Vec::new();
+    delta_entry.read_to_end(&mut delta)?;
+    let rebuilt = file_delta::apply(&fs::read(base)?, &delta)?;
+    reader_hash(&mut rebuilt.as_slice(), algorithm)
+}
+
+// the entries of a tag patch are listed in its diff csv, the full package carries every file,
+// a delta is rebuilt when the base version is in the store, else only `simulate` rebuilds it
+#[allow(clippy::too_many_arguments)]
+fn verify_package(
+    package: &Path,
+    down: &str,
+    keys: &PackageKeys,
+    file_hash_map: &HashMap<String, String>,
+    extra_files: &HashMap<String, String>,
+    algorithm: HashAlgorithm,
+    store: &ArtifactStore,
+    base_objects: Option<&HashMap<String, String>>,
+    errors: &mut Vec<String>,
+) -> Result<()> {
+    let mut archive = open_package(package, keys)?;
+
+    let mut entries = BTreeSet::new();
+    let mut diff_list = None;
+    let mut unchecked_deltas = 0;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        if name.starts_with("diff-") && name.ends_with(".csv") {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            diff_list = Some((name.clone(), content));
+        } else if let Some(expected) = file_hash_map.get(&name).or(extra_files.get(&name)) {
+            match reader_hash(&mut entry, algorithm) {
+                Ok(hash) if &hash == expected => {}
+                Ok(hash) => errors.push(format!(
+                    "{}: {} hash {}, expected {}",
+                    down, name, hash, expected
+                )),
+                Err(e) => errors.push(format!("{}: {} unreadable, {}", down, name, e)),
+            }
+        } else if let Some((bundle, expected)) = name
+            .strip_suffix(".delta")
+            .and_then(|bundle| Some((bundle, file_hash_map.get(bundle)?)))
+        {
+            let base = base_objects
+                .and_then(|objects| objects.get(bundle))
+                .and_then(|hash| store.get(hash));
+            match base.map(|base| rebuilt_hash(&mut entry, &base, algorithm)) {
+                Some(Ok(hash)) if &hash == expected => {}
+                Some(Ok(hash)) => errors.push(format!(
+                    "{}: {} rebuilds hash {}, expected {}",
+                    down, name, hash, expected
+                )),
+                Some(Err(e)) => {
+                    errors.push(format!("{}: {} can not be applied, {}", down, name, e))
+                }
+                None => unchecked_deltas += 1,
+            }
+        }
+        entries.insert(name);
+    }
+    if unchecked_deltas > 0 {
+        tracing::warn!(
+            "{}: {} deltas not rebuilt, their base is not in the store",
+            down,
+            unchecked_deltas
+        );
+    }
+
+    let mut expected_entries = extra_files.keys().cloned().collect::<BTreeSet<_>>();
+    match diff_list {
+        Some((diff_list_name, content)) => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(content.as_bytes());
+            for record in reader.records() {
+                let record = record?;
+                let (name, hash, kind) = match (record.get(0), record.get(1), record.get(2)) {
+                    (Some(name), Some(hash), Some(kind)) => (name, hash, kind),
+                    _ => {
+                        errors.push(format!("{}: invalid diff record {:?}", down, record));
+                        continue;
+                    }
+                };
+                if file_hash_map.get(name).map(|h| h.as_str()) != Some(hash) {
+                    errors.push(format!(
+                        "{}: {} listed with hash {}, build has {:?}",
+                        down,
+                        name,
+                        hash,
+                        file_hash_map.get(name)
+                    ));
+                }
+                if kind == "delta" {
+                    expected_entries.insert(format!("{}.delta", name));
+                } else {
+                    expected_entries.insert(name.to_string());
+                }
+            }
+            expected_entries.insert(diff_list_name.replacen("diff-", "remove-", 1));
+            expected_entries.insert(diff_list_name);
+        }
+        None => expected_entries.extend(file_hash_map.keys().cloned()),
+    }
+
+    for name in expected_entries.difference(&entries) {
+        errors.push(format!("{}: {} missing", down, name));
+    }
+    for name in entries.difference(&expected_entries) {
+        errors.push(format!("{}: unexpected entry {}", down, name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_manifest::FileEntry;
+    use crate::file_zip::{self, CompressionPolicy, PackageKey};
+    use crate::update_info::ReleaseStamp;
+    use std::fs;
+
+    fn write_update_info(patches: &Path, keys: &PackageKeys) -> UpdateInfo {
+        let issued = unix_now().unwrap();
+        let stamp = ReleaseStamp {
+            loader_version: "1".to_string(),
+            release: 1,
+            issued,
+            expires: issued + 60,
+        };
+        let mut update_info = UpdateInfo::new(
+            stamp,
+            PackageInfo::read(patches, "2-full.zip", None, "2", keys).unwrap(),
+        );
+        update_info
+            .patches
+            .push(PackageInfo::read(patches, "1-1.zip", Some("1"), "2", keys).unwrap());
+        update_info.legacy = Some(update_info.legacy_fields());
+        update_info.save(&patches.join("update_info")).unwrap();
+        update_info
+    }
+
+    #[test]
+    fn test_verify() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let build = root.join("build");
+        let patches = root.join("patches");
+        fs::create_dir_all(&build).unwrap();
+        fs::create_dir_all(&patches).unwrap();
+        for (name, content) in [
+            ("a.bundle", "aaa"),
+            ("b.bundle", "bbb"),
+            ("catalog_1.json", "{}"),
+            ("catalog_1.hash", "cc"),
+            ("Version.txt", "2"),
+            ("diff-1-1.csv", ""),
+            ("remove-1-1.csv", ""),
+        ] {
+            fs::write(build.join(name), content).unwrap();
+        }
+
+        let mut manifest = FileManifest::new(HashAlgorithm::Sha256);
+        for name in ["a.bundle", "b.bundle"] {
+            let hash = reader_hash(
+                &mut File::open(build.join(name)).unwrap(),
+                HashAlgorithm::Sha256,
+            )
+            .unwrap();
+            manifest.insert(FileEntry {
+                path: name.to_string(),
+                size: 3,
+                hash,
+            });
+        }
+        manifest.save(&build.join("file-hash.csv")).unwrap();
+        let b_hash = manifest.entries["b.bundle"].hash.clone();
+        fs::write(
+            build.join("diff-1-1.csv"),
+            format!("b.bundle,{},full\n", b_hash),
+        )
+        .unwrap();
+
+        let policy = CompressionPolicy::default();
+        let names = [
+            "a.bundle",
+            "b.bundle",
+            "catalog_1.json",
+            "catalog_1.hash",
+            "Version.txt",
+        ]
+        .map(String::from);
+        let full = names.iter().collect::<Vec<_>>();
+        file_zip::compress(&build, &full, &patches.join("2-full.zip"), false, &policy).unwrap();
+        let tag_names = ["b.bundle", "diff-1-1.csv", "remove-1-1.csv"].map(String::from);
+        let mut patch = full[2..].to_vec();
+        patch.extend(tag_names.iter());
+        file_zip::compress(&build, &patch, &patches.join("1-1.zip"), false, &policy).unwrap();
+
+        let plain = PackageKeys::default();
+        let store = ArtifactStore::with_root(&root.join("store"));
+        write_update_info(&patches, &plain);
+        verify_platform_patches(&build, &patches, "1", &plain, &store).unwrap();
+
+        // a delta is rebuilt from the bundle of version 1 in the store
+        let base = root.join("base");
+        fs::create_dir_all(&base).unwrap();
+        fs::write(base.join("b.bundle"), "bbx").unwrap();
+        let mut base_manifest = FileManifest::new(HashAlgorithm::Sha256);
+        base_manifest.insert(FileEntry {
+            path: "b.bundle".to_string(),
+            size: 3,
+            hash: reader_hash(&mut "bbx".as_bytes(), HashAlgorithm::Sha256).unwrap(),
+        });
+        store
+            .stage(&base, &base_manifest, 1, &mut Default::default(), false)
+            .unwrap();
+        store.promote(1).unwrap();
+        fs::write(
+            build.join("diff-1-1.csv"),
+            format!("b.bundle,{},delta\n", b_hash),
+        )
+        .unwrap();
+        let mut delta_patch = full[2..].to_vec();
+        let delta_names = ["b.bundle.delta", "diff-1-1.csv", "remove-1-1.csv"].map(String::from);
+        delta_patch.extend(delta_names.iter());
+        for (old, valid) in [("bbx", true), ("zzz", false)] {
+            fs::write(
+                build.join("b.bundle.delta"),
+                crate::file_delta::encode(old.as_bytes(), b"bbb"),
+            )
+            .unwrap();
+            file_zip::compress(
+                &build,
+                &delta_patch,
+                &patches.join("1-1.zip"),
+                false,
+                &policy,
+            )
+            .unwrap();
+            write_update_info(&patches, &plain);
+            let result = verify_platform_patches(&build, &patches, "1", &plain, &store);
+            assert_eq!(result.is_ok(), valid);
+        }
+        fs::write(
+            build.join("diff-1-1.csv"),
+            format!("b.bundle,{},full\n", b_hash),
+        )
+        .unwrap();
+        file_zip::compress(&build, &patch, &patches.join("1-1.zip"), false, &policy).unwrap();
+        let mut update_info = write_update_info(&patches, &plain);
+
+        // a size out of date
+        update_info.patches[0].size += 1;
+        update_info.save(&patches.join("update_info")).unwrap();
+        assert!(verify_platform_patches(&build, &patches, "1", &plain, &store).is_err());
+        write_update_info(&patches, &plain);
+
+        // encrypted packages are verified through their key
+        let mut keys = PackageKeys::default();
+        keys.insert(PackageKey::new("k1", [1; 32]));
+        for name in ["2-full.zip", "1-1.zip"] {
+            file_zip::encrypt_package(&patches.join(name), &PackageKey::new("k1", [1; 32]))
+                .unwrap();
+        }
+        let update_info = write_update_info(&patches, &keys);
+        assert_eq!(update_info.full.key_id.as_deref(), Some("k1"));
+        verify_platform_patches(&build, &patches, "1", &keys, &store).unwrap();
+        assert!(verify_platform_patches(&build, &patches, "1", &plain, &store).is_err());
+
+        // a bundle changed after hashing
+        fs::write(build.join("b.bundle"), "bbc").unwrap();
+        file_zip::compress(&build, &patch, &patches.join("1-1.zip"), false, &policy).unwrap();
+        write_update_info(&patches, &keys);
+        assert!(verify_platform_patches(&build, &patches, "1", &keys, &store).is_err());
+    }
+}
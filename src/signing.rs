@@ -0,0 +1,106 @@
+use anyhow::Result;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::giu_config::KeySource;
+
+// detached signature of a file: "{file}.sig", the hex encoded ed25519 signature of the sha256 digest of the file
+const SIGNATURE_EXTENSION: &str = "sig";
+
+pub(crate) fn signature_path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_os_string();
+    name.push(".");
+    name.push(SIGNATURE_EXTENSION);
+    PathBuf::from(name)
+}
+
+fn file_digest(file: &Path) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(file)?, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+fn decode_key(hex_key: &str) -> Result<[u8; 32]> {
+    hex::decode(hex_key.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("ed25519 key must be 32 bytes, 64 hex characters"))
+}
+
+pub(crate) fn load_signing_key(
+    config: &KeySource,
+    project_path: &Path,
+) -> Result<Option<SigningKey>> {
+    Ok(config
+        .load(project_path)?
+        .map(|key| SigningKey::from_bytes(&key)))
+}
+
+// a public key as hex, or a file holding it
+pub(crate) fn load_verifying_key(public_key: &str) -> Result<VerifyingKey> {
+    let path = Path::new(public_key);
+    let hex_key = if path.is_file() {
+        fs::read_to_string(path)?
+    } else {
+        public_key.to_string()
+    };
+    Ok(VerifyingKey::from_bytes(&decode_key(&hex_key)?)?)
+}
+
+pub(crate) fn public_key_hex(key: &SigningKey) -> String {
+    hex::encode(key.verifying_key().to_bytes())
+}
+
+pub(crate) fn sign_file(key: &SigningKey, file: &Path) -> Result<()> {
+    let signature = key.sign(&file_digest(file)?);
+    fs::write(
+        signature_path(file),
+        format!("{}\n", hex::encode(signature.to_bytes())),
+    )?;
+    Ok(())
+}
+
+pub(crate) fn verify_file(key: &VerifyingKey, file: &Path) -> Result<()> {
+    let signature_file = signature_path(file);
+    if !signature_file.is_file() {
+        return Err(anyhow::anyhow!("{} not found", signature_file.display()));
+    }
+    let signature: [u8; 64] = hex::decode(fs::read_to_string(&signature_file)?.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("{} is not an ed25519 signature", signature_file.display()))?;
+    key.verify_strict(&file_digest(file)?, &Signature::from_bytes(&signature))
+        .map_err(|_| anyhow::anyhow!("{} signature mismatch", file.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::write(root.join("signing.key"), "11".repeat(32)).unwrap();
+        let file = root.join("1-1.zip");
+        fs::write(&file, "patch").unwrap();
+
+        let config = KeySource {
+            key_file: Some("signing.key".to_string()),
+            key_env: None,
+        };
+        let key = load_signing_key(&config, root).unwrap().unwrap();
+        sign_file(&key, &file).unwrap();
+        assert!(root.join("1-1.zip.sig").is_file());
+
+        let public_key = load_verifying_key(&public_key_hex(&key)).unwrap();
+        verify_file(&public_key, &file).unwrap();
+
+        let other = SigningKey::from_bytes(&[2; 32]);
+        assert!(verify_file(&other.verifying_key(), &file).is_err());
+        fs::write(&file, "patch!").unwrap();
+        assert!(verify_file(&public_key, &file).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::giu_config::TagPolicy;
+
+/// Tags of the loader version split by how they reach the new patch version
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct TagPlan {
+    // tags patched directly to the new version, in the order of the input tags
+    pub direct: Vec<String>,
+    // (tag, checkpoint tag) the tag updates to the checkpoint first
+    pub chained: Vec<(String, String)>,
+}
+
+pub(crate) fn tag_patch_version(tag: &str) -> u32 {
+    tag.split('-').next_back().unwrap().parse::<u32>().unwrap()
+}
+
+impl TagPolicy {
+    pub fn is_checkpoint(&self, patch_version: u32) -> bool {
+        self.checkpoint_interval > 0 && patch_version.is_multiple_of(self.checkpoint_interval)
+    }
+}
+
+// tags must be sorted by patch version in DESC order, as returned by `get_git_tags`
+pub(crate) fn plan_tags(tags: &[String], policy: &TagPolicy, patch_version: u32) -> TagPlan {
+    let mut plan = TagPlan::default();
+
+    // a checkpoint release patches every tag, so that any older client can chain through it later
+    if policy.recent == 0 || policy.is_checkpoint(patch_version) {
+        plan.direct = tags.to_vec();
+        return plan;
+    }
+
+    for (i, tag) in tags.iter().enumerate() {
+        let version = tag_patch_version(tag);
+        if i < policy.recent || policy.is_checkpoint(version) {
+            plan.direct.push(tag.to_string());
+            continue;
+        }
+        // the oldest checkpoint newer than the tag
+        let checkpoint = tags[..i]
+            .iter()
+            .rev()
+            .find(|t| policy.is_checkpoint(tag_patch_version(t)));
+        match checkpoint {
+            Some(checkpoint) => plan.chained.push((tag.to_string(), checkpoint.to_string())),
+            None => plan.direct.push(tag.to_string()),
+        }
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tags(versions: &[u32]) -> Vec<String> {
+        versions.iter().map(|v| format!("1.0-{}", v)).collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let all = tags(&[12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+
+        // every tag is direct by default
+        let plan = plan_tags(&all, &TagPolicy::default(), 13);
+        assert_eq!(plan.direct, all);
+        assert!(plan.chained.is_empty());
+
+        let policy = TagPolicy {
+            recent: 3,
+            checkpoint_interval: 5,
+        };
+        let plan = plan_tags(&all, &policy, 13);
+        assert_eq!(plan.direct, tags(&[12, 11, 10, 5, 0]));
+        assert_eq!(
+            plan.chained,
+            vec![
+                ("1.0-9".to_string(), "1.0-10".to_string()),
+                ("1.0-8".to_string(), "1.0-10".to_string()),
+                ("1.0-7".to_string(), "1.0-10".to_string()),
+                ("1.0-6".to_string(), "1.0-10".to_string()),
+                ("1.0-4".to_string(), "1.0-5".to_string()),
+                ("1.0-3".to_string(), "1.0-5".to_string()),
+                ("1.0-2".to_string(), "1.0-5".to_string()),
+                ("1.0-1".to_string(), "1.0-5".to_string()),
+            ]
+        );
+
+        // a checkpoint release patches every tag
+        let plan = plan_tags(&all, &policy, 15);
+        assert_eq!(plan.direct, all);
+
+        // without a newer checkpoint the tag stays direct
+        let policy = TagPolicy {
+            recent: 1,
+            checkpoint_interval: 0,
+        };
+        let plan = plan_tags(&tags(&[2, 1]), &policy, 3);
+        assert_eq!(plan.direct, tags(&[2, 1]));
+    }
+}
@@ -0,0 +1,459 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Read,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::file_check::{reader_hash, HashAlgorithm};
+use crate::file_zip::{open_package, package_key_id, PackageKeys};
+
+// update_info layout:
+//   schema 3: "loader_version", "release", "issued" and "expires" added, see `ReleaseStamp`
+//   schema 2: "schema", "version", "full", "patches" and "chains", one object per package,
+//             with the legacy fields below when `legacy_update_info = true`
+//   legacy:   parallel "vers" / "downs" / "sizes" / "removes" lists, sizes like "1.23 M"
+// the patches and the chains are sorted by the version they update from, latest first,
+// the json is pretty printed with the fields in declaration order, so the same release gives the same file
+pub(crate) const UPDATE_INFO_SCHEMA: u32 = 3;
+
+// clock difference tolerated between the release machine and the clients, in seconds
+pub(crate) const MAX_CLOCK_SKEW: u64 = 300;
+
+pub(crate) fn unix_now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+// human readable size of the patch packages, e.g. "1.23 M"
+pub(crate) fn format_size(bytes: u64) -> String {
+    let size = bytes as f64 / 1048576_f64; // 1024 x 1024
+    if size < 0.01 {
+        format!("{:.2} K", size * 1024_f64)
+    } else {
+        format!("{:.2} M", size)
+    }
+}
+
+/// A downloadable package, read back from the zip on disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PackageInfo {
+    // version the package updates from, none for the full package
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    // version the package updates to
+    pub to: String,
+    // file name relative to the platform folder of the loader version
+    pub file: String,
+    // zip size in bytes
+    pub size: u64,
+    // sum of the sizes of the entries
+    pub uncompressed_size: u64,
+    pub file_count: usize,
+    // number of files the client deletes, listed in remove-{tag}.csv
+    pub remove: usize,
+    // sha256 of the zip, lower case hex
+    pub sha256: String,
+    // issue time of the release which packed it, RFC 3339 in UTC, set by `UpdateInfo::set_created`
+    pub created: String,
+    // id of the AES-256-GCM key the package is encrypted with, none for a plain zip,
+    // size and sha256 are those of the encrypted file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+impl PackageInfo {
+    pub fn read(
+        platform_patches_path: &Path,
+        file: &str,
+        from: Option<&str>,
+        to: &str,
+        keys: &PackageKeys,
+    ) -> Result<Self> {
+        let path = platform_patches_path.join(file);
+        let meta = fs::metadata(&path)?;
+        let sha256 = reader_hash(&mut fs::File::open(&path)?, HashAlgorithm::Sha256)?;
+
+        let key_id = package_key_id(&path)?;
+        let mut archive = open_package(&path, keys)?;
+        let mut uncompressed_size = 0;
+        let mut file_count = 0;
+        let mut remove = 0;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            if entry.is_dir() {
+                continue;
+            }
+            uncompressed_size += entry.size();
+            file_count += 1;
+            if entry.name().starts_with("remove-") && entry.name().ends_with(".csv") {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                remove = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(content.as_bytes())
+                    .records()
+                    .count();
+            }
+        }
+
+        Ok(Self {
+            from: from.map(|from| from.to_string()),
+            to: to.to_string(),
+            file: file.to_string(),
+            size: meta.len(),
+            uncompressed_size,
+            file_count,
+            remove,
+            sha256,
+            created: String::new(),
+            key_id,
+        })
+    }
+}
+
+/// Signed freshness of an update_info, so that a mirror can not replay an older one
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ReleaseStamp {
+    #[serde(default)]
+    pub loader_version: String,
+    // increases with every release of every loader version
+    #[serde(default)]
+    pub release: u64,
+    // unix seconds
+    #[serde(default)]
+    pub issued: u64,
+    // unix seconds, the clients reject the update_info after it
+    #[serde(default)]
+    pub expires: u64,
+}
+
+/// The newest update_info a client accepted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TrustedRelease {
+    pub loader_version: String,
+    pub release: u64,
+    pub version: u32,
+}
+
+/// update_info of a platform, the packages which update the clients to the latest version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct UpdateInfo {
+    pub schema: u32,
+    #[serde(flatten)]
+    pub stamp: ReleaseStamp,
+    // latest version
+    pub version: String,
+    // bundle transform of the latest version, from the manifest, none for plain bundles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<String>,
+    // full package of the latest version
+    pub full: PackageInfo,
+    // packages from an older version to the latest version
+    pub patches: Vec<PackageInfo>,
+    // packages from an older version to a checkpoint version, then patched to the latest version
+    pub chains: Vec<PackageInfo>,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub legacy: Option<PlatformPatchInfo>,
+}
+
+impl UpdateInfo {
+    pub fn new(stamp: ReleaseStamp, full: PackageInfo) -> Self {
+        Self {
+            schema: UPDATE_INFO_SCHEMA,
+            stamp,
+            version: full.to.clone(),
+            transform: None,
+            full,
+            patches: Vec::new(),
+            chains: Vec::new(),
+            legacy: None,
+        }
+    }
+
+    // reject an update_info of another loader version, not yet issued, expired,
+    // or older than the one accepted before, the clients check the same after the signature
+    pub fn check_freshness(
+        &self,
+        loader_version: &str,
+        now: u64,
+        trusted: Option<&TrustedRelease>,
+    ) -> Result<()> {
+        let stamp = &self.stamp;
+        if stamp.loader_version != loader_version {
+            return Err(anyhow::anyhow!(
+                "update_info of loader version {:?}, expected {}",
+                stamp.loader_version,
+                loader_version
+            ));
+        }
+        if stamp.release == 0 || stamp.expires <= stamp.issued {
+            return Err(anyhow::anyhow!("update_info without a valid release stamp"));
+        }
+        if stamp.issued > now + MAX_CLOCK_SKEW {
+            return Err(anyhow::anyhow!(
+                "update_info issued in the future: {}",
+                stamp.issued
+            ));
+        }
+        if stamp.expires <= now {
+            return Err(anyhow::anyhow!("update_info expired at {}", stamp.expires));
+        }
+        if let Some(trusted) = trusted {
+            let version = self.version.parse::<u32>()?;
+            if stamp.release < trusted.release {
+                return Err(anyhow::anyhow!(
+                    "update_info release {} rolled back from {}",
+                    stamp.release,
+                    trusted.release
+                ));
+            }
+            if trusted.loader_version == loader_version && version < trusted.version {
+                return Err(anyhow::anyhow!(
+                    "update_info version {} rolled back from {}",
+                    version,
+                    trusted.version
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // the packages are dated by the release stamp, not the file system, the same release gives the same bytes
+    pub fn set_created(&mut self) -> Result<()> {
+        let created = time::OffsetDateTime::from_unix_timestamp(self.stamp.issued as i64)?
+            .format(&time::format_description::well_known::Rfc3339)?;
+        std::iter::once(&mut self.full)
+            .chain(self.patches.iter_mut())
+            .chain(self.chains.iter_mut())
+            .for_each(|package| package.created = created.clone());
+        Ok(())
+    }
+
+    // the full package, the patches and the chains
+    pub fn packages(&self) -> impl Iterator<Item = &PackageInfo> {
+        std::iter::once(&self.full)
+            .chain(self.patches.iter())
+            .chain(self.chains.iter())
+    }
+
+    // latest first, by version number then by file name
+    pub fn sort(&mut self) {
+        fn key(package: &PackageInfo) -> (std::cmp::Reverse<u32>, String) {
+            let from = package.from.as_deref().unwrap_or_default();
+            (
+                std::cmp::Reverse(from.parse().unwrap_or_default()),
+                package.file.clone(),
+            )
+        }
+        self.patches.sort_by_key(key);
+        self.chains.sort_by_key(key);
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        let mut sorted = Self {
+            legacy: None,
+            ..self.clone()
+        };
+        sorted.sort();
+        sorted.patches == self.patches && sorted.chains == self.chains
+    }
+
+    // the fields read by the clients before schema 2
+    pub fn legacy_fields(&self) -> PlatformPatchInfo {
+        let from = |package: &PackageInfo| package.from.clone().unwrap_or_default();
+        PlatformPatchInfo {
+            ver: self.version.clone(),
+            down: self.full.file.clone(),
+            size: format_size(self.full.size),
+            vers: self.patches.iter().map(from).collect(),
+            downs: self.patches.iter().map(|p| p.file.clone()).collect(),
+            sizes: self.patches.iter().map(|p| format_size(p.size)).collect(),
+            removes: self.patches.iter().map(|p| p.remove).collect(),
+            chain_vers: self.chains.iter().map(from).collect(),
+            chain_tos: self.chains.iter().map(|p| p.to.clone()).collect(),
+            chain_downs: self.chains.iter().map(|p| p.file.clone()).collect(),
+            chain_sizes: self.chains.iter().map(|p| format_size(p.size)).collect(),
+        }
+    }
+
+    pub fn load(file: &Path) -> Result<Self> {
+        let update_info: Self = serde_json::from_str(&fs::read_to_string(file)?)?;
+        if update_info.schema > UPDATE_INFO_SCHEMA {
+            return Err(anyhow::anyhow!(
+                "Unsupported update_info schema: {}",
+                update_info.schema
+            ));
+        }
+        Ok(update_info)
+    }
+
+    pub fn save(&self, file: &Path) -> Result<()> {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        fs::write(file, content)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PlatformPatchInfo {
+    // latest version
+    pub ver: String,
+    // latest version full package download name
+    pub down: String,
+    pub size: String,
+
+    // incremental package versions
+    pub vers: Vec<String>,
+    // incremental package download name match with versions
+    pub downs: Vec<String>,
+    // incremental package sizes match with versions
+    pub sizes: Vec<String>,
+    // incremental package removed file counts match with versions
+    pub removes: Vec<usize>,
+
+    // versions which update to a checkpoint version first, then to the latest version
+    pub chain_vers: Vec<String>,
+    // checkpoint versions match with chain versions
+    pub chain_tos: Vec<String>,
+    // checkpoint package download name match with chain versions
+    pub chain_downs: Vec<String>,
+    // checkpoint package sizes match with chain versions
+    pub chain_sizes: Vec<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_zip::{self, CompressionPolicy};
+
+    #[test]
+    fn test_update_info() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::write(root.join("a.bundle"), "aaa").unwrap();
+        fs::write(root.join("remove-1-1.csv"), "b.bundle,B\nc.bundle,C\n").unwrap();
+        let names = ["a.bundle", "remove-1-1.csv"].map(String::from);
+        file_zip::compress(
+            root,
+            &names.iter().collect::<Vec<_>>(),
+            &root.join("1-1.zip"),
+            false,
+            &CompressionPolicy::default(),
+        )
+        .unwrap();
+
+        let patch =
+            PackageInfo::read(root, "1-1.zip", Some("1"), "2", &Default::default()).unwrap();
+        assert_eq!(
+            patch.size,
+            fs::metadata(root.join("1-1.zip")).unwrap().len()
+        );
+        assert_eq!(patch.uncompressed_size, 3 + 22);
+        assert_eq!(patch.file_count, 2);
+        assert_eq!(patch.remove, 2);
+        assert_eq!(patch.sha256.len(), 64);
+
+        let mut update_info = UpdateInfo::new(
+            ReleaseStamp::default(),
+            PackageInfo {
+                from: None,
+                file: "2-full.zip".to_string(),
+                ..patch.clone()
+            },
+        );
+        update_info.patches.push(patch);
+        update_info.stamp.issued = 1704067200;
+        update_info.set_created().unwrap();
+        assert_eq!(update_info.patches[0].created, "2024-01-01T00:00:00Z");
+        let file = root.join("update_info");
+
+        // the legacy fields are flattened beside the schema 2 fields
+        update_info.legacy = Some(update_info.legacy_fields());
+        update_info.save(&file).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(json["schema"], UPDATE_INFO_SCHEMA);
+        assert_eq!(json["vers"][0], "1");
+        assert_eq!(json["downs"][0], "1-1.zip");
+        assert_eq!(json["patches"][0]["from"], "1");
+        let loaded = UpdateInfo::load(&file).unwrap();
+        assert_eq!(loaded.legacy, Some(update_info.legacy_fields()));
+        assert_eq!(loaded.patches, update_info.patches);
+
+        // the same packages in another order give the same file
+        let content = fs::read_to_string(&file).unwrap();
+        assert!(content.starts_with("{\n  \"schema\": 3,\n"));
+        let mut shuffled = loaded.clone();
+        shuffled.patches.insert(
+            0,
+            PackageInfo {
+                from: Some("0".to_string()),
+                ..shuffled.patches[0].clone()
+            },
+        );
+        assert!(!shuffled.is_sorted());
+        shuffled.sort();
+        assert!(shuffled.is_sorted());
+        assert_eq!(shuffled.patches[1].from.as_deref(), Some("0"));
+
+        update_info.legacy = None;
+        update_info.save(&file).unwrap();
+        assert!(UpdateInfo::load(&file).unwrap().legacy.is_none());
+    }
+
+    #[test]
+    fn test_freshness() {
+        let full = PackageInfo {
+            from: None,
+            to: "5".to_string(),
+            file: "5-full.zip".to_string(),
+            size: 0,
+            uncompressed_size: 0,
+            file_count: 0,
+            remove: 0,
+            sha256: String::new(),
+            created: String::new(),
+            key_id: None,
+        };
+        let stamp = ReleaseStamp {
+            loader_version: "1.0.0".to_string(),
+            release: 7,
+            issued: 1000,
+            expires: 2000,
+        };
+        let update_info = UpdateInfo::new(stamp, full);
+        let trusted = |loader_version: &str, release, version| TrustedRelease {
+            loader_version: loader_version.to_string(),
+            release,
+            version,
+        };
+
+        assert!(update_info.check_freshness("1.0.0", 1500, None).is_ok());
+        assert!(update_info.check_freshness("1.0.1", 1500, None).is_err());
+        assert!(update_info.check_freshness("1.0.0", 2000, None).is_err());
+        assert!(update_info
+            .check_freshness("1.0.0", 1000 - MAX_CLOCK_SKEW, None)
+            .is_ok());
+        assert!(update_info
+            .check_freshness("1.0.0", 999 - MAX_CLOCK_SKEW, None)
+            .is_err());
+
+        let check =
+            |trusted: TrustedRelease| update_info.check_freshness("1.0.0", 1500, Some(&trusted));
+        assert!(check(trusted("1.0.0", 7, 5)).is_ok());
+        assert!(check(trusted("1.0.0", 6, 4)).is_ok());
+        assert!(check(trusted("1.0.0", 8, 5)).is_err());
+        assert!(check(trusted("1.0.0", 7, 6)).is_err());
+        assert!(check(trusted("0.9.0", 6, 9)).is_ok());
+
+        assert!(
+            UpdateInfo::new(ReleaseStamp::default(), update_info.full.clone())
+                .check_freshness("", 1500, None)
+                .is_err()
+        );
+    }
+}